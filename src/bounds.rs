@@ -0,0 +1,153 @@
+use descartes::{N, P3};
+
+#[derive(Copy, Clone, Debug)]
+pub struct BoundingBox3 {
+    pub min: P3,
+    pub max: P3,
+}
+
+impl BoundingBox3 {
+    pub fn point(p: P3) -> Self {
+        BoundingBox3 { min: p, max: p }
+    }
+
+    pub fn extended_by(&self, other: BoundingBox3) -> Self {
+        BoundingBox3 {
+            min: P3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            max: P3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        }
+    }
+
+    pub fn overlaps(&self, other: &BoundingBox3) -> bool {
+        self.max.x >= other.min.x
+            && other.max.x >= self.min.x
+            && self.max.y >= other.min.y
+            && other.max.y >= self.min.y
+            && self.max.z >= other.min.z
+            && other.max.z >= self.min.z
+    }
+
+    pub fn contains(&self, point: P3) -> bool {
+        self.min.x <= point.x
+            && self.max.x >= point.x
+            && self.min.y <= point.y
+            && self.max.y >= point.y
+            && self.min.z <= point.z
+            && self.max.z >= point.z
+    }
+
+    pub fn center(&self) -> P3 {
+        P3::from((self.min.coords + self.max.coords) / 2.0)
+    }
+
+    pub fn corners(&self) -> [P3; 8] {
+        let (min, max) = (self.min, self.max);
+        [
+            P3::new(min.x, min.y, min.z),
+            P3::new(max.x, min.y, min.z),
+            P3::new(min.x, max.y, min.z),
+            P3::new(max.x, max.y, min.z),
+            P3::new(min.x, min.y, max.z),
+            P3::new(max.x, min.y, max.z),
+            P3::new(min.x, max.y, max.z),
+            P3::new(max.x, max.y, max.z),
+        ]
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct BoundingSphere {
+    pub center: P3,
+    pub radius: N,
+}
+
+impl BoundingSphere {
+    pub fn contains(&self, point: P3) -> bool {
+        (point - self.center).norm() <= self.radius
+    }
+
+    /// The smallest sphere containing both spheres
+    pub fn extended_by(&self, other: BoundingSphere) -> Self {
+        let offset = other.center - self.center;
+        let distance = offset.norm();
+
+        if distance + other.radius <= self.radius {
+            *self
+        } else if distance + self.radius <= other.radius {
+            other
+        } else {
+            let radius = (distance + self.radius + other.radius) / 2.0;
+            BoundingSphere {
+                center: self.center + offset * ((radius - self.radius) / distance),
+                radius,
+            }
+        }
+    }
+}
+
+/// An axis-aligned bounding box and a bounding sphere of the same geometry
+#[derive(Copy, Clone, Debug)]
+pub struct Bounds {
+    pub bounding_box: BoundingBox3,
+    pub bounding_sphere: BoundingSphere,
+}
+
+impl Bounds {
+    /// Computes a tight box and a close-fitting sphere (using Ritter's algorithm)
+    pub fn from_points(points: &[P3]) -> Option<Bounds> {
+        let first = *points.first()?;
+
+        let bounding_box = points
+            .iter()
+            .fold(BoundingBox3::point(first), |bbox, point| {
+                bbox.extended_by(BoundingBox3::point(*point))
+            });
+
+        let farthest_from = |from: P3| {
+            points
+                .iter()
+                .cloned()
+                .max_by(|a, b| {
+                    (a - from)
+                        .norm_squared()
+                        .partial_cmp(&(b - from).norm_squared())
+                        .unwrap()
+                })
+                .unwrap()
+        };
+
+        let a = farthest_from(first);
+        let b = farthest_from(a);
+        let mut center = P3::from((a.coords + b.coords) / 2.0);
+        let mut radius = (b - a).norm() / 2.0;
+
+        for point in points {
+            let distance = (point - center).norm();
+            if distance > radius {
+                let new_radius = (radius + distance) / 2.0;
+                center += (point - center) * ((new_radius - radius) / distance);
+                radius = new_radius;
+            }
+        }
+
+        Some(Bounds {
+            bounding_box,
+            bounding_sphere: BoundingSphere { center, radius },
+        })
+    }
+
+    pub fn extended_by(&self, other: Bounds) -> Self {
+        Bounds {
+            bounding_box: self.bounding_box.extended_by(other.bounding_box),
+            bounding_sphere: self.bounding_sphere.extended_by(other.bounding_sphere),
+        }
+    }
+}
@@ -0,0 +1,413 @@
+use descartes::{N, P2, P3, V3, LinePath, Area, PointContainer, BoundingBox, Segment};
+use crate::mesh::{Mesh, Vertex, WELD_EPSILON, interpolate, mesh_from_wide_indices, welded_mesh};
+use lyon_tessellation::math::point as lyon_point;
+use lyon_tessellation::path::iterator::PathIter;
+use lyon_tessellation::path::PathEvent;
+use lyon_tessellation::{FillOptions, FillTessellator};
+use std::collections::HashMap;
+
+// points closer to a cutting plane than this count as lying on it
+const CUT_EPSILON: N = 0.001;
+
+// identifies a mesh edge by the exact positions of its ends, so meshes
+// that aren't welded still produce connected contours
+type EdgeKey = ([u32; 3], [u32; 3]);
+
+fn position_bits(vertex: &Vertex) -> [u32; 3] {
+    [
+        vertex.position[0].to_bits(),
+        vertex.position[1].to_bits(),
+        vertex.position[2].to_bits(),
+    ]
+}
+
+fn edge_key(a: &Vertex, b: &Vertex) -> EdgeKey {
+    let (a, b) = (position_bits(a), position_bits(b));
+    if a <= b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+/// A plane given by a point on it and its normal, the side the normal points to is "inside"
+#[derive(Copy, Clone)]
+struct ClipPlane {
+    point: P3,
+    normal: V3,
+}
+
+impl ClipPlane {
+    fn signed_distance(&self, vertex: &Vertex) -> N {
+        (V3::from(vertex.position) - self.point.coords).dot(&self.normal)
+    }
+
+    fn is_inside(&self, vertex: &Vertex) -> bool {
+        self.signed_distance(vertex) >= 0.0
+    }
+
+    /// Where the edge crosses the plane, the same for both directions of the edge
+    fn crossing(&self, a: &Vertex, b: &Vertex) -> Vertex {
+        let (a, b) = if position_bits(a) <= position_bits(b) { (a, b) } else { (b, a) };
+        let (distance_a, distance_b) = (self.signed_distance(a), self.signed_distance(b));
+        interpolate(a, b, distance_a / (distance_a - distance_b))
+    }
+
+    /// Splits a convex polygon into its parts outside and inside of the plane,
+    /// `None` for a part if the polygon lies completely on one side
+    fn split(&self, polygon: &[Vertex]) -> (Option<Vec<Vertex>>, Option<Vec<Vertex>>) {
+        let distances = polygon.iter().map(|vertex| self.signed_distance(vertex)).collect::<Vec<_>>();
+
+        if distances.iter().all(|distance| *distance > -CUT_EPSILON) {
+            return (None, Some(polygon.to_vec()));
+        } else if distances.iter().all(|distance| *distance < CUT_EPSILON) {
+            return (Some(polygon.to_vec()), None);
+        }
+
+        let mut outside = Vec::new();
+        let mut inside = Vec::new();
+        let n = polygon.len();
+
+        for i in 0..n {
+            let j = (i + 1) % n;
+            if distances[i] < CUT_EPSILON {
+                outside.push(polygon[i]);
+            }
+            if distances[i] > -CUT_EPSILON {
+                inside.push(polygon[i]);
+            }
+            if (distances[i] < -CUT_EPSILON && distances[j] > CUT_EPSILON)
+                || (distances[i] > CUT_EPSILON && distances[j] < -CUT_EPSILON)
+            {
+                let crossing = self.crossing(&polygon[i], &polygon[j]);
+                outside.push(crossing);
+                inside.push(crossing);
+            }
+        }
+
+        (Some(outside), Some(inside))
+    }
+
+    /// Two directions in the plane, such that `u`, `v` and the normal are right-handed
+    fn basis(&self) -> (V3, V3) {
+        let helper = if self.normal.x.abs() < 0.9 {
+            V3::new(1.0, 0.0, 0.0)
+        } else {
+            V3::new(0.0, 1.0, 0.0)
+        };
+        let u = helper.cross(&self.normal).normalize();
+        let v = self.normal.cross(&u);
+        (u, v)
+    }
+
+    /// The closed (or, for open meshes, open) outlines where the mesh crosses the plane,
+    /// running counter-clockwise around solid parts of a closed mesh,
+    /// seen from the side the normal points to
+    fn contours(&self, mesh: &Mesh) -> Vec<Vec<P3>> {
+        let mut segments_by_start = HashMap::<EdgeKey, (EdgeKey, P3, P3)>::new();
+        let mut has_incoming = HashMap::<EdgeKey, bool>::new();
+
+        for triangle in mesh.indices.chunks(3) {
+            let corners = [
+                mesh.vertices[triangle[0] as usize],
+                mesh.vertices[triangle[1] as usize],
+                mesh.vertices[triangle[2] as usize],
+            ];
+            let crossings = (0..3)
+                .filter(|i| self.is_inside(&corners[*i]) != self.is_inside(&corners[(i + 1) % 3]))
+                .map(|i| {
+                    let (a, b) = (&corners[i], &corners[(i + 1) % 3]);
+                    let [x, y, z] = self.crossing(a, b).position;
+                    (edge_key(a, b), P3::new(x, y, z))
+                })
+                .collect::<Vec<_>>();
+
+            if crossings.len() != 2 {
+                continue;
+            }
+
+            let [a, b, c] = [
+                V3::from(corners[0].position),
+                V3::from(corners[1].position),
+                V3::from(corners[2].position),
+            ];
+            let triangle_normal = (b - a).cross(&(c - a));
+            let direction = self.normal.cross(&triangle_normal);
+
+            let (start, end) = if (crossings[1].1 - crossings[0].1).dot(&direction) >= 0.0 {
+                (crossings[0], crossings[1])
+            } else {
+                (crossings[1], crossings[0])
+            };
+
+            segments_by_start.insert(start.0, (end.0, start.1, end.1));
+            has_incoming.insert(end.0, true);
+        }
+
+        let mut starts = segments_by_start.keys().cloned().collect::<Vec<_>>();
+        // begin with the starts of open outlines, so they aren't split up
+        starts.sort_by_key(|start| has_incoming.contains_key(start));
+
+        let mut contours = Vec::new();
+
+        for start in starts {
+            let mut next = match segments_by_start.remove(&start) {
+                Some(segment) => segment,
+                None => continue,
+            };
+            let mut contour = vec![next.1];
+
+            loop {
+                let (end_key, _, end_point) = next;
+                contour.push(end_point);
+                next = match segments_by_start.remove(&end_key) {
+                    Some(segment) => segment,
+                    None => break,
+                };
+            }
+
+            contours.push(contour);
+        }
+
+        contours
+    }
+}
+
+impl Mesh {
+    /// Keeps the part of the mesh on the side of the plane that `normal` points to.
+    /// With `cap`, the cut is closed with a flat cross-section facing the other way,
+    /// which only makes sense for closed meshes.
+    /// `None` if the new vertices along the cut don't fit into one mesh anymore.
+    pub fn clipped_by_plane(&self, point: P3, normal: V3, cap: bool) -> Option<Mesh> {
+        let plane = ClipPlane {
+            point,
+            normal: normal.normalize(),
+        };
+
+        let mut vertices = self.vertices.to_vec();
+        let mut indices = Vec::new();
+        let mut crossing_indices = HashMap::<(u16, u16), usize>::new();
+
+        for triangle in self.indices.chunks(3) {
+            let inside = [
+                plane.is_inside(&self.vertices[triangle[0] as usize]),
+                plane.is_inside(&self.vertices[triangle[1] as usize]),
+                plane.is_inside(&self.vertices[triangle[2] as usize]),
+            ];
+
+            // walk around the triangle, keeping inside corners and adding edge crossings,
+            // which results in a convex polygon with up to four corners
+            let mut polygon = Vec::with_capacity(4);
+            for i in 0..3 {
+                let j = (i + 1) % 3;
+                if inside[i] {
+                    polygon.push(triangle[i] as usize);
+                }
+                if inside[i] != inside[j] {
+                    let (a, b) = (&self.vertices[triangle[i] as usize], &self.vertices[triangle[j] as usize]);
+                    let crossing_index = *crossing_indices.entry((triangle[i].min(triangle[j]), triangle[i].max(triangle[j]))).or_insert_with(|| {
+                        vertices.push(plane.crossing(a, b));
+                        vertices.len() - 1
+                    });
+                    polygon.push(crossing_index);
+                }
+            }
+
+            for i in 1..polygon.len().saturating_sub(1) {
+                indices.extend_from_slice(&[polygon[0], polygon[i], polygon[i + 1]]);
+            }
+        }
+
+        if cap {
+            // the cap comes with its own vertices, welding connects them to the cut edges
+            let cap = plane_cap(&plane, &plane.contours(self));
+            let first = vertices.len();
+            indices.extend(cap.indices.iter().map(|index| first + *index as usize));
+            vertices.extend_from_slice(&cap.vertices);
+            welded_mesh(&vertices, &indices, WELD_EPSILON)
+        } else {
+            mesh_from_wide_indices(&vertices, &indices)
+        }
+    }
+
+    /// Keeps the parts of the mesh whose footprint lies inside the area (or outside of it,
+    /// if `keep_inside` is `false`). Triangles are cut vertically along the area's boundary
+    /// and retriangulated, so flat meshes like bands end exactly at the boundary.
+    /// `None` if the new vertices along the cuts don't fit into one mesh anymore.
+    pub fn clipped_to_area(&self, area: &Area, keep_inside: bool) -> Option<Mesh> {
+        let boundary_segments = area
+            .primitives
+            .iter()
+            .flat_map(|primitive| {
+                primitive
+                    .boundary
+                    .path()
+                    .segments()
+                    .map(|segment| (segment.start(), segment.end()))
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        let footprint_bbox = |polygon: &[Vertex]| {
+            polygon
+                .iter()
+                .map(|vertex| BoundingBox::point(P2::new(vertex.position[0], vertex.position[1])))
+                .collect::<BoundingBox>()
+                .grown_by(CUT_EPSILON)
+        };
+
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+
+        for triangle in self.indices.chunks(3) {
+            let mut pieces = vec![vec![
+                self.vertices[triangle[0] as usize],
+                self.vertices[triangle[1] as usize],
+                self.vertices[triangle[2] as usize],
+            ]];
+
+            // after cutting along the lines of all nearby boundary segments,
+            // no piece crosses the boundary and can be classified by its center
+            for (start, end) in boundary_segments.iter() {
+                let segment_bbox = BoundingBox::point(*start).extended_by(BoundingBox::point(*end));
+                let direction = end - start;
+                if direction.norm() < CUT_EPSILON {
+                    continue;
+                }
+                let plane = ClipPlane {
+                    point: P3::new(start.x, start.y, 0.0),
+                    normal: V3::new(-direction.y, direction.x, 0.0).normalize(),
+                };
+
+                pieces = pieces
+                    .into_iter()
+                    .flat_map(|piece| {
+                        if footprint_bbox(&piece).overlaps(&segment_bbox) {
+                            let (outside, inside) = plane.split(&piece);
+                            outside.into_iter().chain(inside).collect()
+                        } else {
+                            vec![piece]
+                        }
+                    })
+                    .collect();
+            }
+
+            for piece in pieces.into_iter().filter(|piece| piece.len() >= 3) {
+                let center = piece
+                    .iter()
+                    .fold(P2::new(0.0, 0.0), |sum, vertex| {
+                        P2::new(sum.x + vertex.position[0], sum.y + vertex.position[1])
+                    })
+                    / piece.len() as N;
+
+                if area.contains(center) == keep_inside {
+                    let first = vertices.len();
+                    for i in 1..(piece.len() - 1) {
+                        indices.push(first);
+                        indices.push(first + i);
+                        indices.push(first + i + 1);
+                    }
+                    vertices.extend(piece);
+                }
+            }
+        }
+
+        welded_mesh(&vertices, &indices, WELD_EPSILON)
+    }
+
+    /// The outlines where the mesh crosses the horizontal plane at `z`.
+    /// Outlines of closed meshes are closed and run counter-clockwise
+    /// around solid parts (clockwise around holes).
+    pub fn contours_at_height(&self, z: N) -> Vec<LinePath> {
+        let plane = ClipPlane {
+            point: P3::new(0.0, 0.0, z),
+            normal: V3::new(0.0, 0.0, -1.0),
+        };
+
+        plane
+            .contours(self)
+            .into_iter()
+            .filter_map(|contour| {
+                // seen from below, so reversed to be counter-clockwise from above
+                LinePath::new(contour.iter().rev().map(|point| P2::new(point.x, point.y)).collect())
+            })
+            .collect()
+    }
+}
+
+/// Fills a closed, roughly planar loop of points, facing the side
+/// from which the loop runs counter-clockwise
+pub(crate) fn loop_cap(points: &[P3]) -> Mesh {
+    // Newell's method, which also works for non-convex loops
+    let normal = (0..points.len()).fold(V3::new(0.0, 0.0, 0.0), |normal, i| {
+        let (a, b) = (points[i], points[(i + 1) % points.len()]);
+        normal + V3::new(
+            (a.y - b.y) * (a.z + b.z),
+            (a.z - b.z) * (a.x + b.x),
+            (a.x - b.x) * (a.y + b.y),
+        )
+    });
+
+    if normal.norm() < N::EPSILON {
+        return Mesh::empty();
+    }
+
+    let plane = ClipPlane {
+        point: points[0],
+        normal: -normal.normalize(),
+    };
+    plane_cap(&plane, &[points.to_vec()])
+}
+
+/// Fills the closed contours in the plane, facing against the plane normal
+fn plane_cap(plane: &ClipPlane, contours: &[Vec<P3>]) -> Mesh {
+    let (u, v) = plane.basis();
+    let to_plane = |point: &P3| {
+        let relative = point - plane.point;
+        lyon_point(relative.dot(&u), relative.dot(&v))
+    };
+
+    let path_iterator = PathIter::new(
+        contours
+            .iter()
+            .filter(|contour| contour.len() >= 3)
+            .flat_map(|contour| {
+                Some(PathEvent::MoveTo(to_plane(&contour[0])))
+                    .into_iter()
+                    .chain(contour[1..].iter().map(|point| PathEvent::LineTo(to_plane(point))))
+                    .chain(Some(PathEvent::Close))
+            }),
+    );
+
+    let mut tesselator = FillTessellator::new();
+    let mut cap = Mesh::empty();
+
+    if tesselator
+        .tessellate_path(path_iterator, &FillOptions::default(), &mut cap)
+        .is_err()
+    {
+        return Mesh::empty();
+    }
+
+    for vertex in cap.vertices.iter_mut() {
+        let [x, y, _] = vertex.position;
+        let position = plane.point + u * x + v * y;
+        vertex.position = [position.x, position.y, position.z];
+    }
+
+    // counter-clockwise in (u, v) faces along the normal, so all triangles
+    // need to be clockwise there
+    let vertices = &cap.vertices;
+    for triangle in cap.indices.chunks_mut(3) {
+        let [a, b, c] = [
+            V3::from(vertices[triangle[0] as usize].position),
+            V3::from(vertices[triangle[1] as usize].position),
+            V3::from(vertices[triangle[2] as usize].position),
+        ];
+        if (b - a).cross(&(c - a)).dot(&plane.normal) > 0.0 {
+            triangle.swap(1, 2);
+        }
+    }
+
+    cap
+}
@@ -0,0 +1,360 @@
+use descartes::{N, V3};
+use crate::mesh::{Mesh, Vertex, WELD_EPSILON, interpolate, welded_mesh};
+
+// points closer to a plane than this count as lying on it
+const PLANE_EPSILON: N = 0.001;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Side {
+    Coplanar,
+    Front,
+    Back,
+    Spanning,
+}
+
+impl Side {
+    fn combined(self, other: Side) -> Side {
+        match (self, other) {
+            (Side::Coplanar, side) | (side, Side::Coplanar) => side,
+            (a, b) if a == b => a,
+            _ => Side::Spanning,
+        }
+    }
+}
+
+fn position(vertex: &Vertex) -> V3 {
+    V3::from(vertex.position)
+}
+
+#[derive(Copy, Clone)]
+struct Plane {
+    normal: V3,
+    distance: N,
+}
+
+impl Plane {
+    fn from_triangle(a: &Vertex, b: &Vertex, c: &Vertex) -> Option<Plane> {
+        let normal = (position(b) - position(a)).cross(&(position(c) - position(a)));
+        if normal.norm() < N::EPSILON {
+            return None;
+        }
+        let normal = normal.normalize();
+        Some(Plane {
+            normal,
+            distance: normal.dot(&position(a)),
+        })
+    }
+
+    fn flipped(&self) -> Plane {
+        Plane {
+            normal: -self.normal,
+            distance: -self.distance,
+        }
+    }
+
+    fn side_of(&self, vertex: &Vertex) -> Side {
+        let signed_distance = self.normal.dot(&position(vertex)) - self.distance;
+        if signed_distance < -PLANE_EPSILON {
+            Side::Back
+        } else if signed_distance > PLANE_EPSILON {
+            Side::Front
+        } else {
+            Side::Coplanar
+        }
+    }
+
+    /// Sorts the polygon (or its parts, if it is split by the plane) into the given lists.
+    /// Coplanar polygons go to `coplanar_front` or `coplanar_back` depending on their facing.
+    fn split_polygon(
+        &self,
+        polygon: Polygon,
+        coplanar_front: &mut Vec<Polygon>,
+        coplanar_back: &mut Vec<Polygon>,
+        front: &mut Vec<Polygon>,
+        back: &mut Vec<Polygon>,
+    ) {
+        let sides = polygon.vertices.iter().map(|vertex| self.side_of(vertex)).collect::<Vec<_>>();
+        let polygon_side = sides.iter().fold(Side::Coplanar, |combined, side| combined.combined(*side));
+
+        match polygon_side {
+            Side::Coplanar => {
+                if self.normal.dot(&polygon.plane.normal) > 0.0 {
+                    coplanar_front.push(polygon);
+                } else {
+                    coplanar_back.push(polygon);
+                }
+            }
+            Side::Front => front.push(polygon),
+            Side::Back => back.push(polygon),
+            Side::Spanning => {
+                let mut front_vertices = Vec::new();
+                let mut back_vertices = Vec::new();
+                let n = polygon.vertices.len();
+
+                for i in 0..n {
+                    let j = (i + 1) % n;
+                    let (side_i, side_j) = (sides[i], sides[j]);
+                    let (vertex_i, vertex_j) = (&polygon.vertices[i], &polygon.vertices[j]);
+
+                    if side_i != Side::Back {
+                        front_vertices.push(*vertex_i);
+                    }
+                    if side_i != Side::Front {
+                        back_vertices.push(*vertex_i);
+                    }
+                    if side_i.combined(side_j) == Side::Spanning {
+                        let t = (self.distance - self.normal.dot(&position(vertex_i)))
+                            / self.normal.dot(&(position(vertex_j) - position(vertex_i)));
+                        let split_vertex = interpolate(vertex_i, vertex_j, t);
+                        front_vertices.push(split_vertex);
+                        back_vertices.push(split_vertex);
+                    }
+                }
+
+                if front_vertices.len() >= 3 {
+                    front.push(Polygon {
+                        vertices: front_vertices,
+                        plane: polygon.plane,
+                    });
+                }
+                if back_vertices.len() >= 3 {
+                    back.push(Polygon {
+                        vertices: back_vertices,
+                        plane: polygon.plane,
+                    });
+                }
+            }
+        }
+    }
+}
+
+/// A convex, counter-clockwise polygon
+#[derive(Clone)]
+struct Polygon {
+    vertices: Vec<Vertex>,
+    plane: Plane,
+}
+
+impl Polygon {
+    fn flip(&mut self) {
+        self.vertices.reverse();
+        self.plane = self.plane.flipped();
+    }
+}
+
+struct BspNode {
+    plane: Plane,
+    // polygons lying in `plane`
+    polygons: Vec<Polygon>,
+    front: Option<usize>,
+    back: Option<usize>,
+}
+
+/// A binary space partitioning of a closed mesh's polygons, where the back
+/// of each plane is inside the solid, see Thibault and Naylor,
+/// "Set Operations on Polyhedra Using Binary Space Partitioning Trees" (1987).
+/// Nodes are kept in a flat list and traversed without recursion,
+/// since trees of convex meshes degenerate into long chains.
+struct Bsp {
+    nodes: Vec<BspNode>,
+}
+
+impl Bsp {
+    fn new(mesh: &Mesh) -> Bsp {
+        let polygons = mesh
+            .indices
+            .chunks(3)
+            .filter_map(|triangle| {
+                let [a, b, c] = [
+                    mesh.vertices[triangle[0] as usize],
+                    mesh.vertices[triangle[1] as usize],
+                    mesh.vertices[triangle[2] as usize],
+                ];
+                Some(Polygon {
+                    plane: Plane::from_triangle(&a, &b, &c)?,
+                    vertices: vec![a, b, c],
+                })
+            })
+            .collect();
+
+        let mut bsp = Bsp { nodes: Vec::new() };
+        bsp.build(polygons);
+        bsp
+    }
+
+    fn add_node(&mut self, plane: Plane) -> usize {
+        self.nodes.push(BspNode {
+            plane,
+            polygons: Vec::new(),
+            front: None,
+            back: None,
+        });
+        self.nodes.len() - 1
+    }
+
+    fn build(&mut self, polygons: Vec<Polygon>) {
+        if polygons.is_empty() {
+            return;
+        }
+        if self.nodes.is_empty() {
+            self.add_node(polygons[0].plane);
+        }
+
+        let mut stack = vec![(0, polygons)];
+
+        while let Some((node_index, polygons)) = stack.pop() {
+            let plane = self.nodes[node_index].plane;
+            let (mut coplanar_front, mut coplanar_back) = (Vec::new(), Vec::new());
+            let (mut front, mut back) = (Vec::new(), Vec::new());
+
+            for polygon in polygons {
+                plane.split_polygon(polygon, &mut coplanar_front, &mut coplanar_back, &mut front, &mut back);
+            }
+
+            self.nodes[node_index].polygons.extend(coplanar_front);
+            self.nodes[node_index].polygons.extend(coplanar_back);
+
+            if !front.is_empty() {
+                let front_index = match self.nodes[node_index].front {
+                    Some(front_index) => front_index,
+                    None => {
+                        let front_index = self.add_node(front[0].plane);
+                        self.nodes[node_index].front = Some(front_index);
+                        front_index
+                    }
+                };
+                stack.push((front_index, front));
+            }
+
+            if !back.is_empty() {
+                let back_index = match self.nodes[node_index].back {
+                    Some(back_index) => back_index,
+                    None => {
+                        let back_index = self.add_node(back[0].plane);
+                        self.nodes[node_index].back = Some(back_index);
+                        back_index
+                    }
+                };
+                stack.push((back_index, back));
+            }
+        }
+    }
+
+    /// Swaps inside and outside
+    fn invert(&mut self) {
+        for node in self.nodes.iter_mut() {
+            for polygon in node.polygons.iter_mut() {
+                polygon.flip();
+            }
+            node.plane = node.plane.flipped();
+            ::std::mem::swap(&mut node.front, &mut node.back);
+        }
+    }
+
+    /// Removes the parts of the polygons that are inside this solid
+    fn clip_polygons(&self, polygons: Vec<Polygon>) -> Vec<Polygon> {
+        if self.nodes.is_empty() {
+            return polygons;
+        }
+
+        let mut outside = Vec::new();
+        let mut stack = vec![(0, polygons)];
+
+        while let Some((node_index, polygons)) = stack.pop() {
+            let node = &self.nodes[node_index];
+            let (mut front, mut back) = (Vec::new(), Vec::new());
+
+            for polygon in polygons {
+                let (mut coplanar_front, mut coplanar_back) = (Vec::new(), Vec::new());
+                node.plane.split_polygon(polygon, &mut coplanar_front, &mut coplanar_back, &mut front, &mut back);
+                front.extend(coplanar_front);
+                back.extend(coplanar_back);
+            }
+
+            match node.front {
+                Some(front_index) => stack.push((front_index, front)),
+                None => outside.extend(front),
+            }
+            // polygons behind a leaf are inside and dropped
+            if let Some(back_index) = node.back {
+                stack.push((back_index, back));
+            }
+        }
+
+        outside
+    }
+
+    /// Removes the parts of this tree's polygons that are inside the other solid
+    fn clip_to(&mut self, other: &Bsp) {
+        for node in self.nodes.iter_mut() {
+            let polygons = ::std::mem::take(&mut node.polygons);
+            node.polygons = other.clip_polygons(polygons);
+        }
+    }
+
+    fn all_polygons(&self) -> Vec<Polygon> {
+        self.nodes.iter().flat_map(|node| node.polygons.iter().cloned()).collect()
+    }
+
+    fn into_mesh(self) -> Option<Mesh> {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+
+        for node in self.nodes {
+            for polygon in node.polygons {
+                let first = vertices.len();
+                for i in 1..(polygon.vertices.len() - 1) {
+                    indices.extend_from_slice(&[first, first + i, first + i + 1]);
+                }
+                vertices.extend(polygon.vertices);
+            }
+        }
+
+        welded_mesh(&vertices, &indices, WELD_EPSILON)
+    }
+}
+
+// Boolean operations on closed meshes with counter-clockwise, outward-facing triangles,
+// like meshes of closed sculptures. The results are closed, welded and wound the same way.
+// Faces that lie in the same plane are handled, but the results can contain T-junctions.
+// Splitting polygons adds vertices, so they return `None` if the result doesn't fit into one mesh.
+impl Mesh {
+    /// Everything that is inside either mesh
+    pub fn union(&self, other: &Mesh) -> Option<Mesh> {
+        let (mut a, mut b) = (Bsp::new(self), Bsp::new(other));
+        a.clip_to(&b);
+        b.clip_to(&a);
+        b.invert();
+        b.clip_to(&a);
+        b.invert();
+        a.build(b.all_polygons());
+        a.into_mesh()
+    }
+
+    /// Everything that is inside this mesh, but not inside the other one
+    pub fn difference(&self, other: &Mesh) -> Option<Mesh> {
+        let (mut a, mut b) = (Bsp::new(self), Bsp::new(other));
+        a.invert();
+        a.clip_to(&b);
+        b.clip_to(&a);
+        b.invert();
+        b.clip_to(&a);
+        b.invert();
+        a.build(b.all_polygons());
+        a.invert();
+        a.into_mesh()
+    }
+
+    /// Everything that is inside both meshes
+    pub fn intersection(&self, other: &Mesh) -> Option<Mesh> {
+        let (mut a, mut b) = (Bsp::new(self), Bsp::new(other));
+        a.invert();
+        b.clip_to(&a);
+        b.invert();
+        a.clip_to(&b);
+        b.clip_to(&a);
+        a.build(b.all_polygons());
+        a.invert();
+        a.into_mesh()
+    }
+}
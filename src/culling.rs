@@ -0,0 +1,126 @@
+use descartes::{N, P3, V3, V4, M4, Iso3, Persp3};
+use crate::bounds::{Bounds, BoundingBox3, BoundingSphere};
+use crate::mesh::InstanceTransform;
+use crate::mesh_grouper::GroupChange;
+
+/// The six planes of a camera's view volume, used to cull groups and instances
+/// that are completely outside of it
+#[derive(Copy, Clone, Debug)]
+pub struct Frustum {
+    // (normal, distance), normalized and pointing inwards
+    planes: [V4; 6],
+}
+
+impl Frustum {
+    /// `view` transforms world coordinates into the camera's eye coordinates
+    pub fn new(perspective: &Persp3, view: &Iso3) -> Frustum {
+        Frustum::from_matrix(&(perspective.as_matrix() * view.to_homogeneous()))
+    }
+
+    /// Extracts the planes from a combined projection and view matrix
+    /// with OpenGL-style clip coordinates
+    pub fn from_matrix(view_projection: &M4) -> Frustum {
+        let row = |i: usize| {
+            V4::new(
+                view_projection[(i, 0)],
+                view_projection[(i, 1)],
+                view_projection[(i, 2)],
+                view_projection[(i, 3)],
+            )
+        };
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+
+        let normalize = |plane: V4| plane / plane.xyz().norm();
+
+        Frustum {
+            planes: [
+                normalize(w + x),
+                normalize(w - x),
+                normalize(w + y),
+                normalize(w - y),
+                normalize(w + z),
+                normalize(w - z),
+            ],
+        }
+    }
+
+    fn signed_distance(plane: &V4, point: P3) -> N {
+        plane.x * point.x + plane.y * point.y + plane.z * point.z + plane.w
+    }
+
+    pub fn contains_point(&self, point: P3) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| Frustum::signed_distance(plane, point) >= 0.0)
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| Frustum::signed_distance(plane, sphere.center) >= -sphere.radius)
+    }
+
+    pub fn intersects_box(&self, bounding_box: &BoundingBox3) -> bool {
+        self.planes.iter().all(|plane| {
+            // the corner furthest along the plane normal
+            let positive_corner = P3::new(
+                if plane.x >= 0.0 { bounding_box.max.x } else { bounding_box.min.x },
+                if plane.y >= 0.0 { bounding_box.max.y } else { bounding_box.min.y },
+                if plane.z >= 0.0 { bounding_box.max.z } else { bounding_box.min.z },
+            );
+            Frustum::signed_distance(plane, positive_corner) >= 0.0
+        })
+    }
+
+    /// Tests the cheap sphere first and only then the tighter box
+    pub fn intersects_bounds(&self, bounds: &Bounds) -> bool {
+        self.intersects_sphere(&bounds.bounding_sphere)
+            && self.intersects_box(&bounds.bounding_box)
+    }
+
+    /// Keeps all items whose bounds intersect the frustum
+    pub fn visible<T, I: IntoIterator<Item = (T, Bounds)>>(&self, items: I) -> Vec<T> {
+        items
+            .into_iter()
+            .filter_map(|(item, bounds)| {
+                if self.intersects_bounds(&bounds) {
+                    Some(item)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// The ids of all non-empty groups that intersect the frustum
+    pub fn visible_groups<'a, T: 'a, I: IntoIterator<Item = &'a GroupChange<T>>>(
+        &self,
+        group_changes: I,
+    ) -> Vec<usize> {
+        self.visible(group_changes.into_iter().filter_map(|change| {
+            change.bounds.map(|bounds| (change.group_id, bounds))
+        }))
+    }
+
+    /// All instances whose copy of the prototype's bounding sphere intersects the frustum
+    pub fn visible_instances<I: InstanceTransform>(
+        &self,
+        instances: &[I],
+        prototype_sphere: &BoundingSphere,
+    ) -> Vec<I> {
+        instances
+            .iter()
+            .filter(|instance| {
+                let matrix = instance.to_matrix();
+                let max_scale = (0..3)
+                    .map(|column| V3::new(matrix[(0, column)], matrix[(1, column)], matrix[(2, column)]).norm())
+                    .fold(0.0, N::max);
+                self.intersects_sphere(&BoundingSphere {
+                    center: matrix.transform_point(&prototype_sphere.center),
+                    radius: prototype_sphere.radius * max_scale,
+                })
+            })
+            .cloned()
+            .collect()
+    }
+}
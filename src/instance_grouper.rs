@@ -0,0 +1,178 @@
+use crate::mesh::Instance;
+use crate::mesh_grouper::merged_ranges;
+use std::hash::Hash;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// The instances of one prototype mesh, kept in one dense instance buffer
+struct InstanceBuffer<K, I> {
+    instances: Vec<I>,
+    keys: Vec<K>,
+    changed: bool,
+    // instance ranges that changed since the last `InstanceChange`
+    changed_instances: Vec<Range<usize>>,
+}
+
+impl<K: Hash + Eq + Clone, I: Copy> InstanceBuffer<K, I> {
+    pub fn new() -> InstanceBuffer<K, I> {
+        InstanceBuffer {
+            instances: Vec::new(),
+            keys: Vec::new(),
+            changed: false,
+            changed_instances: Vec::new(),
+        }
+    }
+
+    /// Empty ranges only mark the buffer as changed, e.g. if it shrank
+    fn mark_changed(&mut self, range: Range<usize>) {
+        self.changed = true;
+        if range.start < range.end {
+            self.changed_instances.push(range);
+        }
+    }
+
+    pub fn push(&mut self, key: K, instance: I) -> usize {
+        let index = self.instances.len();
+        self.instances.push(instance);
+        self.keys.push(key);
+        self.mark_changed(index..index + 1);
+        index
+    }
+
+    pub fn set(&mut self, index: usize, instance: I) {
+        self.instances[index] = instance;
+        self.mark_changed(index..index + 1);
+    }
+
+    /// Removes the instance by moving the last instance into its place.
+    /// Returns the key of the moved instance, if any.
+    pub fn swap_remove(&mut self, index: usize) -> Option<&K> {
+        self.instances.swap_remove(index);
+        self.keys.swap_remove(index);
+
+        if index < self.instances.len() {
+            self.mark_changed(index..index + 1);
+            Some(&self.keys[index])
+        } else {
+            // only shrinking, but still needs to be reported
+            self.mark_changed(index..index);
+            None
+        }
+    }
+
+    pub fn get_change_if_changed<P: Clone>(&mut self, prototype: &P) -> Option<InstanceChange<P, I>> {
+        if !self.changed {
+            return None;
+        }
+        self.changed = false;
+        let changed_instances = ::std::mem::take(&mut self.changed_instances);
+
+        Some(InstanceChange {
+            prototype: prototype.clone(),
+            patches: merged_ranges(changed_instances, self.instances.len())
+                .into_iter()
+                .map(|range| (range.start, self.instances[range].to_vec()))
+                .collect(),
+            n_instances: self.instances.len(),
+        })
+    }
+}
+
+/// Keeps one instance buffer per prototype mesh up to date, the instanced counterpart
+/// to `MeshGrouper`. Prototypes are identified by `P`, e.g. an index into the
+/// renderer's prototype meshes. Instances can be `Instance`, `Instance3d` or any custom format.
+pub struct InstanceGrouper<K: Hash + Eq + Clone, P: Hash + Eq + Clone = usize, I: Copy = Instance> {
+    buffers: HashMap<P, InstanceBuffer<K, I>>,
+    membership: HashMap<K, (P, usize)>,
+}
+
+/// An incremental change of a prototype's instance buffer.
+///
+/// The buffer is first resized to `n_instances`, keeping its contents, then each
+/// `(offset, instances)` patch overwrites it starting at `offset`.
+/// Everything else is unchanged, so the buffer can be patched in place.
+pub struct InstanceChange<P = usize, I = Instance> {
+    pub prototype: P,
+    pub patches: Vec<(usize, Vec<I>)>,
+    pub n_instances: usize,
+}
+
+impl<K: Hash + Eq + Clone, P: Hash + Eq + Clone, I: Copy> InstanceGrouper<K, P, I> {
+    pub fn new() -> InstanceGrouper<K, P, I> {
+        InstanceGrouper {
+            buffers: HashMap::new(),
+            membership: HashMap::new(),
+        }
+    }
+
+    fn remove(&mut self, key: &K) {
+        let (prototype, index) = self
+            .membership
+            .remove(key)
+            .expect("Should contain key to be removed");
+        let buffer = self.buffers.get_mut(&prototype).expect("Should have buffer of member");
+
+        if let Some(moved_key) = buffer.swap_remove(index) {
+            self.membership
+                .get_mut(moved_key)
+                .expect("Should have moved member")
+                .1 = index;
+        }
+    }
+
+    /// Removes and adds instances, returning changes of all affected instance buffers.
+    ///
+    /// Adding a key that is already present moves or otherwise changes its instance,
+    /// in place if the prototype stays the same. If a key occurs several times in `to_add`,
+    /// the last instance wins. Removing an instance moves the last instance of the same
+    /// prototype into its place, so instance buffers stay dense.
+    pub fn update<RemI: IntoIterator<Item = K>, AddI: IntoIterator<Item = (K, P, I)>>(
+        &mut self,
+        to_remove: RemI,
+        to_add: AddI,
+    ) -> Vec<InstanceChange<P, I>> {
+        for key_to_remove in to_remove {
+            self.remove(&key_to_remove);
+        }
+
+        for (key, prototype, instance) in to_add {
+            if let Some((current_prototype, index)) = self.membership.get(&key).cloned() {
+                if current_prototype == prototype {
+                    self.buffers
+                        .get_mut(&prototype)
+                        .expect("Should have buffer of member")
+                        .set(index, instance);
+                    continue;
+                } else {
+                    self.remove(&key);
+                }
+            }
+
+            let index = self
+                .buffers
+                .entry(prototype.clone())
+                .or_insert_with(InstanceBuffer::new)
+                .push(key.clone(), instance);
+            self.membership.insert(key, (prototype, index));
+        }
+
+        self.buffers
+            .iter_mut()
+            .filter_map(|(prototype, buffer)| buffer.get_change_if_changed(prototype))
+            .collect()
+    }
+
+    /// All current instances of a prototype, e.g. to recreate its buffer from scratch
+    pub fn instances(&self, prototype: &P) -> &[I] {
+        self.buffers
+            .get(prototype)
+            .map(|buffer| &buffer.instances[..])
+            .unwrap_or(&[])
+    }
+}
+
+impl<K: Hash + Eq + Clone, P: Hash + Eq + Clone, I: Copy> Default for InstanceGrouper<K, P, I> {
+    fn default() -> Self {
+        InstanceGrouper::new()
+    }
+}
@@ -1,7 +1,26 @@
+mod bounds;
+mod clip;
+mod csg;
+mod culling;
+mod instance_grouper;
 mod mesh;
 mod mesh_grouper;
+mod optimize;
+mod ray;
+mod random;
+mod roads;
+mod scatter;
+mod simplify;
 mod sculpt;
 
-pub use self::mesh::{Mesh, Vertex, Instance};
-pub use self::mesh_grouper::{MeshGrouper, GroupChange};
-pub use self::sculpt::{SculptLine, Surface, SpannedSurface, FlatSurface, Sculpture, SkeletonSpine, RoofSurface, GableSurface};
\ No newline at end of file
+pub use self::bounds::{Bounds, BoundingBox3, BoundingSphere};
+pub use self::culling::Frustum;
+pub use self::mesh::{Mesh, Vertex, Instance, Instance3d, InstanceTransform};
+pub use self::instance_grouper::{InstanceGrouper, InstanceChange};
+pub use self::mesh_grouper::{MeshGrouper, LodMeshGrouper, GroupChange, Compaction};
+pub use self::random::Variation;
+pub use self::ray::{Ray, RayHit, MeshBvh};
+pub use self::roads::RoadNetwork;
+pub use self::scatter::{Scatter, ScatterDistribution};
+pub use self::sculpt::{SculptLine, Surface, SpannedSurface, FlatSurface, Sculpture, SkeletonSpine, RoofSurface, GableSurface};
+pub use descartes::CellCoords;
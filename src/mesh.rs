@@ -1,10 +1,11 @@
-pub use descartes::{N, P3, P2, V3, V4, M4, Iso3, Persp3, Into2d, Into3d, WithUniqueOrthogonal,
-Area, LinePath, Segment};
+pub use descartes::{N, P2, P3, V3, M4, Iso3, Area, LinePath, Segment};
 
 use compact::CVec;
 use compact_macros::Compact;
+use std::collections::HashMap;
 use std::rc::Rc;
 use crate::sculpt::{Sculpture, SpannedSurface, SculptLine};
+use crate::bounds::Bounds;
 
 #[derive(Copy, Clone, Debug)]
 pub struct Vertex {
@@ -26,6 +27,223 @@ impl Instance {
             instance_color: color,
         }
     }
+
+    /// Instances every `spacing` along the path, starting at `offset`,
+    /// facing along the path direction, e.g. for railway sleepers or fence posts
+    pub fn along_path(path: &LinePath, spacing: N, offset: N, z: N, color: [f32; 3]) -> Vec<Instance> {
+        if spacing <= 0.0 {
+            return Vec::new();
+        }
+
+        let length = path.length();
+        let n_instances = if offset > length {
+            0
+        } else {
+            ((length - offset) / spacing).floor() as usize + 1
+        };
+
+        (0..n_instances)
+            .map(|i| {
+                let distance = offset + i as N * spacing;
+                let position = path.along(distance);
+                let direction = path.direction_along(distance);
+                Instance {
+                    instance_position: [position.x, position.y, z],
+                    instance_direction: [direction.x, direction.y],
+                    instance_color: color,
+                }
+            })
+            .collect()
+    }
+}
+
+/// An instance with full 3D rotation (as a quaternion `[x, y, z, w]`) and non-uniform scale,
+/// for props that are tilted or not standing on the ground. `Instance` is more compact
+/// to upload for instances that are only rotated around the z axis.
+#[derive(Copy, Clone, Debug)]
+pub struct Instance3d {
+    pub instance_position: [f32; 3],
+    pub instance_rotation: [f32; 4],
+    pub instance_scale: [f32; 3],
+    pub instance_color: [f32; 3],
+}
+
+impl Instance3d {
+    pub fn with_color(color: [f32; 3]) -> Instance3d {
+        Instance3d {
+            instance_position: [0.0, 0.0, 0.0],
+            instance_rotation: [0.0, 0.0, 0.0, 1.0],
+            instance_scale: [1.0, 1.0, 1.0],
+            instance_color: color,
+        }
+    }
+
+    pub fn from_iso(iso: &Iso3, scale: V3, color: [f32; 3]) -> Instance3d {
+        let translation = iso.translation.vector;
+        let rotation = iso.rotation.quaternion().coords;
+        Instance3d {
+            instance_position: [translation.x, translation.y, translation.z],
+            instance_rotation: [rotation.x, rotation.y, rotation.z, rotation.w],
+            instance_scale: [scale.x, scale.y, scale.z],
+            instance_color: color,
+        }
+    }
+
+    /// Decomposes a matrix into translation, rotation and scale. Mirroring is expressed
+    /// as a negative x scale. Returns `None` for degenerate or projective matrices,
+    /// shear is lost.
+    pub fn from_matrix(matrix: &M4, color: [f32; 3]) -> Option<Instance3d> {
+        let m = |row: usize, column: usize| matrix[(row, column)];
+
+        if m(3, 0).abs() > 0.000_01 || m(3, 1).abs() > 0.000_01 || m(3, 2).abs() > 0.000_01 {
+            return None;
+        }
+
+        let column = |column: usize| V3::new(m(0, column), m(1, column), m(2, column));
+        let mut scale = V3::new(column(0).norm(), column(1).norm(), column(2).norm());
+
+        if scale.iter().any(|s| *s < 0.000_01) {
+            return None;
+        }
+
+        if is_mirroring(matrix) {
+            scale.x = -scale.x;
+        }
+
+        let r = |row: usize, col: usize| m(row, col) / scale[col];
+        let trace = r(0, 0) + r(1, 1) + r(2, 2);
+
+        // [x, y, z, w], see "Converting a Rotation Matrix to a Quaternion" (Day 2015)
+        let rotation = if trace > 0.0 {
+            let s = (trace + 1.0).sqrt() * 2.0;
+            [(r(2, 1) - r(1, 2)) / s, (r(0, 2) - r(2, 0)) / s, (r(1, 0) - r(0, 1)) / s, s / 4.0]
+        } else if r(0, 0) > r(1, 1) && r(0, 0) > r(2, 2) {
+            let s = (1.0 + r(0, 0) - r(1, 1) - r(2, 2)).sqrt() * 2.0;
+            [s / 4.0, (r(0, 1) + r(1, 0)) / s, (r(0, 2) + r(2, 0)) / s, (r(2, 1) - r(1, 2)) / s]
+        } else if r(1, 1) > r(2, 2) {
+            let s = (1.0 + r(1, 1) - r(0, 0) - r(2, 2)).sqrt() * 2.0;
+            [(r(0, 1) + r(1, 0)) / s, s / 4.0, (r(1, 2) + r(2, 1)) / s, (r(0, 2) - r(2, 0)) / s]
+        } else {
+            let s = (1.0 + r(2, 2) - r(0, 0) - r(1, 1)).sqrt() * 2.0;
+            [(r(0, 2) + r(2, 0)) / s, (r(1, 2) + r(2, 1)) / s, s / 4.0, (r(1, 0) - r(0, 1)) / s]
+        };
+
+        Some(Instance3d {
+            instance_position: [m(0, 3), m(1, 3), m(2, 3)],
+            instance_rotation: rotation,
+            instance_scale: [scale.x, scale.y, scale.z],
+            instance_color: color,
+        })
+    }
+}
+
+impl From<Instance> for Instance3d {
+    fn from(instance: Instance) -> Instance3d {
+        // half-angle rotation around z from the (normalized) direction
+        let [dx, dy] = instance.instance_direction;
+        let length = (dx * dx + dy * dy).sqrt();
+        let angle = dy.atan2(dx) / 2.0;
+        Instance3d {
+            instance_position: instance.instance_position,
+            instance_rotation: [0.0, 0.0, angle.sin(), angle.cos()],
+            instance_scale: [length, length, 1.0],
+            instance_color: instance.instance_color,
+        }
+    }
+}
+
+/// Instance formats that can place a copy of a prototype mesh
+pub trait InstanceTransform: Copy {
+    /// The transformation from prototype to world coordinates
+    fn to_matrix(&self) -> M4;
+    fn color(&self) -> [f32; 3];
+}
+
+impl InstanceTransform for Instance {
+    fn to_matrix(&self) -> M4 {
+        let [dx, dy] = self.instance_direction;
+        let [x, y, z] = self.instance_position;
+        M4::new(
+            dx, -dy, 0.0, x,
+            dy, dx, 0.0, y,
+            0.0, 0.0, 1.0, z,
+            0.0, 0.0, 0.0, 1.0,
+        )
+    }
+
+    fn color(&self) -> [f32; 3] {
+        self.instance_color
+    }
+}
+
+impl InstanceTransform for Instance3d {
+    fn to_matrix(&self) -> M4 {
+        let [qx, qy, qz, qw] = self.instance_rotation;
+        let [sx, sy, sz] = self.instance_scale;
+        let [x, y, z] = self.instance_position;
+        M4::new(
+            (1.0 - 2.0 * (qy * qy + qz * qz)) * sx,
+            2.0 * (qx * qy - qz * qw) * sy,
+            2.0 * (qx * qz + qy * qw) * sz,
+            x,
+            2.0 * (qx * qy + qz * qw) * sx,
+            (1.0 - 2.0 * (qx * qx + qz * qz)) * sy,
+            2.0 * (qy * qz - qx * qw) * sz,
+            y,
+            2.0 * (qx * qz - qy * qw) * sx,
+            2.0 * (qy * qz + qx * qw) * sy,
+            (1.0 - 2.0 * (qx * qx + qy * qy)) * sz,
+            z,
+            0.0, 0.0, 0.0, 1.0,
+        )
+    }
+
+    fn color(&self) -> [f32; 3] {
+        self.instance_color
+    }
+}
+
+/// Whether a transformation matrix turns right-handed into left-handed geometry
+pub(crate) fn is_mirroring(matrix: &M4) -> bool {
+    let m = |row: usize, column: usize| matrix[(row, column)];
+    let determinant = m(0, 0) * (m(1, 1) * m(2, 2) - m(1, 2) * m(2, 1))
+        - m(0, 1) * (m(1, 0) * m(2, 2) - m(1, 2) * m(2, 0))
+        + m(0, 2) * (m(1, 0) * m(2, 1) - m(1, 1) * m(2, 0));
+    determinant < 0.0
+}
+
+/// The corners of a triangle, given by its three indices into the mesh's vertices
+pub(crate) fn triangle_points(mesh: &Mesh, triangle: &[u16]) -> [P3; 3] {
+    let point = |i: usize| {
+        let [x, y, z] = mesh.vertices[triangle[i] as usize].position;
+        P3::new(x, y, z)
+    };
+    [point(0), point(1), point(2)]
+}
+
+/// Not normalized, its length is twice the triangle's area
+pub(crate) fn triangle_normal(a: P3, b: P3, c: P3) -> V3 {
+    (b - a).cross(&(c - a))
+}
+
+/// The point at `t` (0 to 1) along a quadratic Bézier curve
+pub(crate) fn quadratic_bezier_point(from: P2, control: P2, to: P2, t: N) -> P2 {
+    let a = from.coords * ((1.0 - t) * (1.0 - t));
+    let b = control.coords * (2.0 * (1.0 - t) * t);
+    let c = to.coords * (t * t);
+    P2::from(a + b + c)
+}
+
+// vertices closer than this are merged when welding generated meshes
+pub(crate) const WELD_EPSILON: N = 0.001;
+
+/// The vertex at `t` along the edge from `a` (0) to `b` (1)
+pub(crate) fn interpolate(a: &Vertex, b: &Vertex, t: N) -> Vertex {
+    let mut interpolated = *a;
+    for i in 0..3 {
+        interpolated.position[i] += (b.position[i] - a.position[i]) * t;
+    }
+    interpolated
 }
 
 #[derive(Compact, Debug)]
@@ -48,6 +266,127 @@ impl Mesh {
             indices: CVec::new(),
         }
     }
+
+    /// The bounds of all vertices, `None` for an empty mesh
+    pub fn bounds(&self) -> Option<Bounds> {
+        let points = self
+            .vertices
+            .iter()
+            .map(|vertex| P3::new(vertex.position[0], vertex.position[1], vertex.position[2]))
+            .collect::<Vec<_>>();
+        Bounds::from_points(&points)
+    }
+
+    /// Reverses the winding of every triangle, turning front faces into back faces
+    pub fn flip_winding(&mut self) {
+        for triangle in self.indices.chunks_mut(3) {
+            triangle.swap(1, 2);
+        }
+    }
+
+    /// Appends a reversed copy of every triangle, so the mesh survives backface culling
+    /// from both sides. Vertices are shared between front and back faces.
+    pub fn add_back_faces(&mut self) {
+        let n_indices = self.indices.len();
+        for i in (0..n_indices).step_by(3) {
+            let (a, b, c) = (self.indices[i], self.indices[i + 1], self.indices[i + 2]);
+            self.indices.push(a);
+            self.indices.push(c);
+            self.indices.push(b);
+        }
+    }
+
+    /// Transforms all vertices by a matrix or an isometry. Mirroring transformations
+    /// also flip the winding, so front faces stay front faces.
+    pub fn transform<T: Into<M4>>(&mut self, transformation: T) {
+        let matrix = transformation.into();
+
+        for vertex in self.vertices.iter_mut() {
+            let [x, y, z] = vertex.position;
+            let transformed = matrix.transform_point(&P3::new(x, y, z));
+            vertex.position = [transformed.x, transformed.y, transformed.z];
+        }
+
+        if is_mirroring(&matrix) {
+            self.flip_winding();
+        }
+    }
+
+    pub fn transformed<T: Into<M4>>(&self, transformation: T) -> Mesh {
+        let mut transformed = self.clone();
+        transformed.transform(transformation);
+        transformed
+    }
+
+    /// Bakes a copy of the prototype for each instance into static meshes,
+    /// e.g. for distant LODs or export. The copies are split into as many meshes
+    /// as needed to keep each within the range of `u16` indices.
+    pub fn from_instances<I: InstanceTransform>(prototype: &Mesh, instances: &[I]) -> Vec<Mesh> {
+        let instances_per_mesh = (u16::MAX as usize + 1) / prototype.vertices.len().max(1);
+
+        instances
+            .chunks(instances_per_mesh)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .map(|instance| prototype.transformed(instance.to_matrix()))
+                    .sum()
+            })
+            .collect()
+    }
+
+    /// Like `from_instances`, together with the color of each baked vertex,
+    /// which is the color of its instance. Colors are in the same order as
+    /// the vertices, e.g. for a separate vertex buffer.
+    pub fn from_instances_with_colors<I: InstanceTransform>(
+        prototype: &Mesh,
+        instances: &[I],
+    ) -> Vec<(Mesh, Vec<[f32; 3]>)> {
+        let instances_per_mesh = (u16::MAX as usize + 1) / prototype.vertices.len().max(1);
+
+        Mesh::from_instances(prototype, instances)
+            .into_iter()
+            .zip(instances.chunks(instances_per_mesh))
+            .map(|(mesh, chunk)| {
+                let colors = chunk
+                    .iter()
+                    .flat_map(|instance| vec![instance.color(); prototype.vertices.len()])
+                    .collect();
+                (mesh, colors)
+            })
+            .collect()
+    }
+
+    /// Merges vertices that are closer than `epsilon`,
+    /// so surfaces that share an edge also share its vertices. Afterwards, drops
+    /// triangles that became degenerate and vertices that are no longer used.
+    pub fn weld(&mut self, epsilon: N) {
+        let indices = self.indices.iter().map(|index| *index as usize).collect::<Vec<_>>();
+        *self = welded_mesh(&self.vertices, &indices, epsilon).expect("Welding should never add vertices");
+    }
+
+    /// Drops vertices that aren't referenced by any triangle
+    pub fn remove_unused_vertices(&mut self) {
+        let indices = self.indices.iter().map(|index| *index as usize).collect::<Vec<_>>();
+        *self = mesh_from_wide_indices(&self.vertices, &indices).expect("Should only drop vertices");
+    }
+
+    /// Makes every triangle counter-clockwise when seen from above (facing +z),
+    /// assuming all triangles are (roughly) horizontal
+    pub(crate) fn orient_upwards(&mut self) {
+        let vertices = &self.vertices;
+        for triangle in self.indices.chunks_mut(3) {
+            let [a, b, c] = [
+                vertices[triangle[0] as usize].position,
+                vertices[triangle[1] as usize].position,
+                vertices[triangle[2] as usize].position,
+            ];
+            let signed_area = (b[0] - a[0]) * (c[1] - a[1]) - (c[0] - a[0]) * (b[1] - a[1]);
+            if signed_area < 0.0 {
+                triangle.swap(1, 2);
+            }
+        }
+    }
 }
 
 impl Clone for Mesh {
@@ -186,6 +525,8 @@ impl Mesh {
             .tessellate_path(path_iterator, &FillOptions::default(), &mut output)
             .unwrap();
 
+        output.orient_upwards();
+
         output
     }
 
@@ -193,6 +534,38 @@ impl Mesh {
         Self::from_path_as_band_asymmetric(path, width / 2.0, width / 2.0, z)
     }
 
+    /// Dashes of a band along the path, like lane markings. `phase` is how far into
+    /// the dash pattern the path starts. Dashes follow the path around corners.
+    pub fn from_path_as_dashed_band(
+        path: &LinePath,
+        width: N,
+        dash_length: N,
+        gap_length: N,
+        phase: N,
+        z: N,
+    ) -> Mesh {
+        let period = dash_length + gap_length;
+        if dash_length <= 0.0 || period <= 0.0 {
+            return Mesh::empty();
+        }
+
+        let length = path.length();
+        let mut dash_start = -(phase.rem_euclid(period));
+        let mut dashes = Mesh::empty();
+
+        while dash_start < length {
+            let (start, end) = (dash_start.max(0.0), (dash_start + dash_length).min(length));
+            if end > start {
+                if let Some(dash_path) = path.subsection(start, end) {
+                    dashes += Self::from_path_as_band(&dash_path, width, z);
+                }
+            }
+            dash_start += period;
+        }
+
+        dashes
+    }
+
     pub fn from_path_as_band_asymmetric(
         path: &LinePath,
         width_left: N,
@@ -211,3 +584,81 @@ impl Mesh {
         }).unwrap_or(Mesh::empty())
     }
 }
+
+/// Like `Mesh::new`, for indices that might exceed the range of `u16` before unused
+/// vertices are dropped. `None` if the used vertices still don't fit into one mesh.
+pub(crate) fn mesh_from_wide_indices(vertices: &[Vertex], indices: &[usize]) -> Option<Mesh> {
+    let mut remapping = vec![None; vertices.len()];
+    let mut used_vertices = Vec::new();
+
+    let narrowed_indices = indices
+        .iter()
+        .map(|index| {
+            *remapping[*index].get_or_insert_with(|| {
+                used_vertices.push(vertices[*index]);
+                used_vertices.len() - 1
+            })
+        })
+        .collect::<Vec<_>>();
+
+    if used_vertices.len() > u16::MAX as usize + 1 {
+        return None;
+    }
+
+    Some(Mesh::new(
+        used_vertices,
+        narrowed_indices.into_iter().map(|index| index as u16).collect(),
+    ))
+}
+
+/// Like `Mesh::weld`, for indices that might exceed the range of `u16` before welding,
+/// e.g. when every polygon of a result comes with its own vertices.
+/// `None` if the welded vertices still don't fit into one mesh.
+pub(crate) fn welded_mesh(vertices: &[Vertex], indices: &[usize], epsilon: N) -> Option<Mesh> {
+    let cell_of = |position: [f32; 3]| {
+        (
+            (position[0] / epsilon).floor() as i64,
+            (position[1] / epsilon).floor() as i64,
+            (position[2] / epsilon).floor() as i64,
+        )
+    };
+
+    let mut cells = HashMap::<(i64, i64, i64), Vec<usize>>::new();
+    let mut welded_vertices = Vec::<Vertex>::new();
+    let mut remapping = Vec::with_capacity(vertices.len());
+
+    for vertex in vertices {
+        let (x, y, z) = cell_of(vertex.position);
+        let is_same = |other: &Vertex| {
+            other
+                .position
+                .iter()
+                .zip(vertex.position.iter())
+                .map(|(a, b)| (a - b) * (a - b))
+                .sum::<f32>()
+                <= epsilon * epsilon
+        };
+
+        let existing = (-1..=1)
+            .flat_map(|dx| (-1..=1).flat_map(move |dy| (-1..=1).map(move |dz| (x + dx, y + dy, z + dz))))
+            .filter_map(|cell| cells.get(&cell))
+            .flat_map(|candidates| candidates.iter())
+            .find(|candidate| is_same(&welded_vertices[**candidate]))
+            .cloned();
+
+        remapping.push(existing.unwrap_or_else(|| {
+            welded_vertices.push(*vertex);
+            cells.entry((x, y, z)).or_default().push(welded_vertices.len() - 1);
+            welded_vertices.len() - 1
+        }));
+    }
+
+    let welded_indices = indices
+        .chunks(3)
+        .map(|triangle| [remapping[triangle[0]], remapping[triangle[1]], remapping[triangle[2]]])
+        .filter(|[a, b, c]| a != b && b != c && c != a)
+        .flat_map(|triangle| triangle.to_vec())
+        .collect::<Vec<_>>();
+
+    mesh_from_wide_indices(&welded_vertices, &welded_indices)
+}
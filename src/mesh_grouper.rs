@@ -1,143 +1,895 @@
-use crate::mesh::Mesh;
+use crate::mesh::{Mesh, Vertex};
+use crate::bounds::Bounds;
+use descartes::{N, CellCoords};
+use crate::ray::{Ray, RayHit, MeshBvh, closest_hit};
 use std::hash::Hash;
-use std::collections::{HashMap, VecDeque};
+use std::collections::HashMap;
+use std::ops::Range;
 
-struct MeshQueue<K: Hash + Eq> {
-    meshes: VecDeque<(K, Mesh)>,
-    total_vertices: usize,
+struct Member<K> {
+    key: K,
+    vertex_offset: usize,
+    n_vertices: usize,
+    index_offset: usize,
+    n_indices: usize,
+    // computed once when added, so group bounds are cheap to update
+    bounds: Option<Bounds>,
+}
+
+/// The meshes of a group, concatenated into one vertex and one index buffer.
+///
+/// Members are kept in the order of their ranges, which is the same in both buffers.
+/// Removing a member can leave a gap between the ranges of two members: its vertices
+/// are unused and its indices form degenerate triangles, until a new mesh fills it.
+struct MeshGroup<K: Hash + Eq, T: Eq + Clone> {
+    members: Vec<Member<K>>,
+    mesh: Mesh,
     max_vertices: usize,
-    dirty: bool,
+    // only set for spatial grouping, can change while the group is empty
+    cell: Option<CellCoords>,
+    // can also change while the group is empty
+    tag: T,
+    // only built for groups with enough triangles, see `MeshGrouper::with_picking_bvh`
+    bvh: Option<MeshBvh>,
+    changed: bool,
+    // vertex and index ranges that changed since the last `GroupChange`
+    changed_vertices: Vec<Range<usize>>,
+    changed_indices: Vec<Range<usize>>,
 }
 
-impl<K: Hash + Eq> MeshQueue<K> {
-    pub fn new(max_vertices: usize) -> MeshQueue<K> {
-        MeshQueue {
-            meshes: VecDeque::new(),
-            total_vertices: 0,
+impl<K: Hash + Eq, T: Eq + Clone> MeshGroup<K, T> {
+    pub fn new(max_vertices: usize, cell: Option<CellCoords>, tag: T) -> MeshGroup<K, T> {
+        MeshGroup {
+            members: Vec::new(),
+            mesh: Mesh::empty(),
             max_vertices,
-            dirty: false,
+            cell,
+            tag,
+            bvh: None,
+            changed: false,
+            changed_vertices: Vec::new(),
+            changed_indices: Vec::new(),
         }
     }
 
-    pub fn push(
-        &mut self,
-        new_members: Vec<(K, Mesh)>,
-        total_new_vertices: usize,
-    ) -> (Vec<(K, Mesh)>, usize) {
-        if total_new_vertices > self.max_vertices {
-            panic!("New meshes too big for one queue");
+    /// Empty ranges only mark the group as changed, e.g. if its buffers shrank
+    fn mark_changed(&mut self, vertices: Range<usize>, indices: Range<usize>) {
+        self.changed = true;
+        if vertices.start < vertices.end {
+            self.changed_vertices.push(vertices);
+        }
+        if indices.start < indices.end {
+            self.changed_indices.push(indices);
+        }
+    }
+
+    fn position_of(&self, key: &K) -> Option<usize> {
+        self.members.iter().position(|member| &member.key == key)
+    }
+
+    /// The vertex and index offsets right after the member at `position`,
+    /// or at the start of the buffers if there is none
+    fn end_of(&self, position: Option<usize>) -> (usize, usize) {
+        position
+            .and_then(|position| self.members.get(position))
+            .map(|member| (member.vertex_offset + member.n_vertices, member.index_offset + member.n_indices))
+            .unwrap_or((0, 0))
+    }
+
+    /// The space from the start of the member at `position` until the next member,
+    /// or until the vertex limit for the last member
+    fn slot_of(&self, position: usize) -> (usize, usize) {
+        let member = &self.members[position];
+        match self.members.get(position + 1) {
+            Some(next) => (next.vertex_offset - member.vertex_offset, next.index_offset - member.index_offset),
+            None => (self.max_vertices - member.vertex_offset, usize::MAX),
+        }
+    }
+
+    /// The first place for a mesh of this size, either in a gap or at the end of the buffers,
+    /// as the position in `members` and the vertex and index offsets
+    fn free_slot(&self, n_vertices: usize, n_indices: usize) -> Option<(usize, usize, usize)> {
+        let (mut vertex_start, mut index_start) = (0, 0);
+
+        for (position, member) in self.members.iter().enumerate() {
+            if member.vertex_offset - vertex_start >= n_vertices && member.index_offset - index_start >= n_indices {
+                return Some((position, vertex_start, index_start));
+            }
+            vertex_start = member.vertex_offset + member.n_vertices;
+            index_start = member.index_offset + member.n_indices;
+        }
+
+        if vertex_start + n_vertices <= self.max_vertices {
+            Some((self.members.len(), vertex_start, index_start))
+        } else {
+            None
+        }
+    }
+
+    pub fn fits(&self, n_new_vertices: usize, n_new_indices: usize) -> bool {
+        self.free_slot(n_new_vertices, n_new_indices).is_some()
+    }
+
+    /// Whether the member's mesh could be replaced by one of this size
+    pub fn fits_replacement(&self, key: &K, n_new_vertices: usize, n_new_indices: usize) -> bool {
+        let position = self.position_of(key).expect("Should contain key to be replaced");
+        let (slot_vertices, slot_indices) = self.slot_of(position);
+        (n_new_vertices <= slot_vertices && n_new_indices <= slot_indices)
+            || self.fits(n_new_vertices, n_new_indices)
+    }
+
+    /// Writes the mesh into the buffers at the given offsets, growing them if needed
+    fn write(&mut self, vertex_offset: usize, index_offset: usize, mesh: &Mesh) {
+        let n_overwritten = self.mesh.vertices.len().saturating_sub(vertex_offset).min(mesh.vertices.len());
+        if n_overwritten > 0 {
+            self.mesh.vertices[vertex_offset..vertex_offset + n_overwritten]
+                .copy_from_slice(&mesh.vertices[..n_overwritten]);
+        }
+        self.mesh.vertices.extend_from_copy_slice(&mesh.vertices[n_overwritten..]);
+
+        let offset_indices = mesh.indices.iter().map(|index| *index + vertex_offset as u16).collect::<Vec<_>>();
+        let n_overwritten = self.mesh.indices.len().saturating_sub(index_offset).min(offset_indices.len());
+        if n_overwritten > 0 {
+            self.mesh.indices[index_offset..index_offset + n_overwritten]
+                .copy_from_slice(&offset_indices[..n_overwritten]);
+        }
+        self.mesh.indices.extend_from_copy_slice(&offset_indices[n_overwritten..]);
+
+        self.mark_changed(
+            vertex_offset..vertex_offset + mesh.vertices.len(),
+            index_offset..index_offset + mesh.indices.len(),
+        );
+    }
+
+    /// Turns indices that no member uses anymore into degenerate triangles
+    fn clear_indices(&mut self, indices: Range<usize>) {
+        if indices.start < indices.end {
+            for index in self.mesh.indices[indices.clone()].iter_mut() {
+                *index = 0;
+            }
+            let vertex_end = self.mesh.vertices.len();
+            self.mark_changed(vertex_end..vertex_end, indices);
+        }
+    }
+
+    /// Shrinks the buffers to end with the last member
+    fn truncate_to_members(&mut self) {
+        let (vertex_end, index_end) = self.end_of(self.members.len().checked_sub(1));
+        if vertex_end < self.mesh.vertices.len() || index_end < self.mesh.indices.len() {
+            self.mesh.vertices.truncate(vertex_end);
+            self.mesh.indices.truncate(index_end);
+            self.mark_changed(vertex_end..vertex_end, index_end..index_end);
         }
+    }
+
+    /// Replaces the member's mesh, in place if the new mesh fits into the member's
+    /// range and the gap after it, otherwise by removing it and adding the new mesh
+    pub fn replace(&mut self, key: K, mesh: &Mesh, bounds: Option<Bounds>) {
+        let position = self.position_of(&key).expect("Should contain key to be replaced");
+        let (slot_vertices, slot_indices) = self.slot_of(position);
 
-        let mut dropped = Vec::new();
-        let mut total_dropped_vertices = 0;
+        if mesh.vertices.len() <= slot_vertices && mesh.indices.len() <= slot_indices {
+            let (vertex_offset, index_offset, old_n_indices) = {
+                let member = &mut self.members[position];
+                let old_n_indices = member.n_indices;
+                member.n_vertices = mesh.vertices.len();
+                member.n_indices = mesh.indices.len();
+                member.bounds = bounds;
+                (member.vertex_offset, member.index_offset, old_n_indices)
+            };
 
-        while self.total_vertices + total_new_vertices > self.max_vertices {
-            let next_member_to_drop = self
-                .meshes
-                .pop_front()
-                .expect("Should still have meshes left");
-            self.total_vertices -= next_member_to_drop.1.vertices.len();
-            total_dropped_vertices += next_member_to_drop.1.vertices.len();
-            dropped.push(next_member_to_drop);
+            self.write(vertex_offset, index_offset, mesh);
+            if position + 1 == self.members.len() {
+                self.truncate_to_members();
+            } else {
+                self.clear_indices(index_offset + mesh.indices.len()..index_offset + old_n_indices);
+            }
+        } else {
+            self.remove(&key);
+            self.push(key, mesh, bounds);
         }
+    }
 
-        self.meshes.extend(new_members);
-        self.total_vertices += total_new_vertices;
-        self.dirty = true;
+    /// Puts the mesh into the first gap it fits in, or at the end of the buffers
+    pub fn push(&mut self, key: K, mesh: &Mesh, bounds: Option<Bounds>) {
+        let (position, vertex_offset, index_offset) = self
+            .free_slot(mesh.vertices.len(), mesh.indices.len())
+            .expect("New mesh too big for one group");
 
-        (dropped, total_dropped_vertices)
+        self.write(vertex_offset, index_offset, mesh);
+        self.members.insert(
+            position,
+            Member {
+                key,
+                vertex_offset,
+                n_vertices: mesh.vertices.len(),
+                index_offset,
+                n_indices: mesh.indices.len(),
+                bounds,
+            },
+        );
     }
 
+    /// Removes the member's mesh and fills the gap it leaves by moving the last members
+    /// into it, as long as they fit. Only the moved meshes and cleared indices change.
     pub fn remove(&mut self, key: &K) {
-        let index = self
-            .meshes
+        let mut position = self.position_of(key).expect("Should contain key to be removed");
+        self.members.remove(position);
+        let (mut gap_vertex_start, mut gap_index_start) = self.end_of(position.checked_sub(1));
+
+        while let Some(next) = self.members.get(position) {
+            let (gap_vertex_end, gap_index_end) = (next.vertex_offset, next.index_offset);
+            let last = self.members.last().expect("Should have a member after the gap");
+
+            if last.n_vertices > gap_vertex_end - gap_vertex_start || last.n_indices > gap_index_end - gap_index_start {
+                self.clear_indices(gap_index_start..gap_index_end);
+                return;
+            }
+
+            let mesh = self.member_mesh(last);
+            let mut moved = self.members.pop().expect("Should have a last member");
+            moved.vertex_offset = gap_vertex_start;
+            moved.index_offset = gap_index_start;
+            self.write(gap_vertex_start, gap_index_start, &mesh);
+            self.members.insert(position, moved);
+            self.truncate_to_members();
+
+            gap_vertex_start += mesh.vertices.len();
+            gap_index_start += mesh.indices.len();
+            position += 1;
+        }
+
+        self.truncate_to_members();
+    }
+
+    /// A copy of the member's own mesh, cut out of the group's buffers
+    pub fn member_mesh(&self, member: &Member<K>) -> Mesh {
+        Mesh::new(
+            self.mesh.vertices[member.vertex_offset..member.vertex_offset + member.n_vertices].to_vec(),
+            self.mesh.indices[member.index_offset..member.index_offset + member.n_indices]
+                .iter()
+                .map(|index| *index - member.vertex_offset as u16)
+                .collect(),
+        )
+    }
+
+    /// The member that the triangle belongs to
+    fn member_of_triangle(&self, triangle_index: usize) -> Option<&Member<K>> {
+        let index = 3 * triangle_index;
+        let position = self.members.partition_point(|member| member.index_offset + member.n_indices <= index);
+        self.members.get(position).filter(|member| member.index_offset <= index)
+    }
+
+    /// The number of vertices used by members, not counting gaps
+    pub fn n_vertices(&self) -> usize {
+        self.members.iter().map(|member| member.n_vertices).sum()
+    }
+
+    pub fn bounds(&self) -> Option<Bounds> {
+        self.members
             .iter()
-            .position(|(k, _)| k == key)
-            .expect("Should contain key to be removed");
-        let (_, old_mesh) = self.meshes.remove(index).unwrap();
-        self.total_vertices -= old_mesh.vertices.len();
-        self.dirty = true;
+            .filter_map(|member| member.bounds)
+            .fold(None, |group_bounds: Option<Bounds>, bounds| {
+                Some(match group_bounds {
+                    Some(group_bounds) => group_bounds.extended_by(bounds),
+                    None => bounds,
+                })
+            })
     }
 
-    pub fn get_mesh_if_changed(&mut self) -> Option<Mesh> {
-        if self.dirty {
-            self.dirty = false;
-            Some(self.meshes.iter().map(|(_, mesh)| mesh).sum())
-        } else {
-            None
+    /// Whether meshes with this cell and tag may go into this group
+    pub fn accepts(&self, cell: Option<CellCoords>, tag: &T) -> bool {
+        self.cell == cell && &self.tag == tag
+    }
+
+    pub fn get_change_if_changed(&mut self, group_id: usize) -> Option<GroupChange<T>> {
+        if !self.changed {
+            return None;
+        }
+        self.changed = false;
+        let changed_vertices = ::std::mem::take(&mut self.changed_vertices);
+        let changed_indices = ::std::mem::take(&mut self.changed_indices);
+
+        Some(GroupChange {
+            group_id,
+            vertex_patches: merged_ranges(changed_vertices, self.mesh.vertices.len())
+                .into_iter()
+                .map(|range| (range.start, self.mesh.vertices[range].to_vec()))
+                .collect(),
+            index_patches: merged_ranges(changed_indices, self.mesh.indices.len())
+                .into_iter()
+                .map(|range| (range.start, self.mesh.indices[range].to_vec()))
+                .collect(),
+            n_vertices: self.mesh.vertices.len(),
+            n_indices: self.mesh.indices.len(),
+            bounds: self.bounds(),
+            cell: self.cell,
+            tag: self.tag.clone(),
+        })
+    }
+}
+
+/// The ranges cut off at `len`, sorted, with overlapping and touching ranges merged
+pub(crate) fn merged_ranges(mut ranges: Vec<Range<usize>>, len: usize) -> Vec<Range<usize>> {
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<Range<usize>> = Vec::new();
+
+    for range in ranges {
+        let range = range.start.min(len)..range.end.min(len);
+        if range.start >= range.end {
+            continue;
+        }
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
         }
     }
+
+    merged
 }
 
-pub struct MeshGrouper<K: Hash + Eq + Clone> {
-    groups: Vec<MeshQueue<K>>,
+/// Groups meshes into large meshes with at most `max_vertices_per_group`, to reduce draw calls.
+///
+/// Each mesh can come with a tag of type `T` (like a material or render state),
+/// meshes with different tags never share a group.
+pub struct MeshGrouper<K: Hash + Eq + Clone, T: Eq + Clone = ()> {
+    groups: Vec<MeshGroup<K, T>>,
     group_membership: HashMap<K, usize>,
     max_vertices_per_group: usize,
+    cell_width: Option<N>,
+    optimize_meshes: bool,
+    reduce_overdraw: bool,
+    bvh_min_triangles: Option<usize>,
 }
 
-pub struct GroupChange {
+/// An incremental change of a group's vertex and index buffers.
+///
+/// The buffers are first resized to `n_vertices` and `n_indices`, keeping their contents,
+/// then each `(offset, data)` patch overwrites them starting at `offset`.
+/// Everything else is unchanged, so the buffers can be patched in place.
+pub struct GroupChange<T = ()> {
     pub group_id: usize,
-    pub new_group_mesh: Mesh,
+    pub vertex_patches: Vec<(usize, Vec<Vertex>)>,
+    pub index_patches: Vec<(usize, Vec<u16>)>,
+    pub n_vertices: usize,
+    pub n_indices: usize,
+    /// Bounds of the whole group, `None` if it became empty
+    pub bounds: Option<Bounds>,
+    /// The grid cell of all meshes in the group, if grouping spatially
+    pub cell: Option<CellCoords>,
+    /// The tag of all meshes in the group
+    pub tag: T,
+}
+
+/// The result of `MeshGrouper::compact`
+pub struct Compaction<T = ()> {
+    pub changes: Vec<GroupChange<T>>,
+    /// Groups that became empty, so their buffers can be freed.
+    /// Their ids might be reused for new groups later.
+    pub emptied_groups: Vec<usize>,
+    /// `false` if the vertex budget ran out before compaction was done
+    pub finished: bool,
+}
+
+impl<K: Hash + Eq + Clone, T: Eq + Clone + Default> MeshGrouper<K, T> {
+    /// Like `update_tagged`, giving all added meshes the default tag
+    pub fn update<RemI: IntoIterator<Item = K>, AddI: IntoIterator<Item = (K, Mesh)>>(
+        &mut self,
+        to_remove: RemI,
+        to_add: AddI,
+    ) -> Vec<GroupChange<T>> {
+        self.update_tagged(
+            to_remove,
+            to_add.into_iter().map(|(key, mesh)| (key, mesh, T::default())),
+        )
+    }
 }
 
 impl<K: Hash + Eq + Clone> MeshGrouper<K> {
     pub fn new(max_vertices_per_group: usize) -> MeshGrouper<K> {
+        MeshGrouper::new_tagged(max_vertices_per_group, None)
+    }
+
+    /// Groups meshes by the grid cell that the center of their bounds lies in,
+    /// so each group stays spatially compact, which helps culling and LOD
+    pub fn new_spatial(max_vertices_per_group: usize, cell_width: N) -> MeshGrouper<K> {
+        MeshGrouper::new_tagged(max_vertices_per_group, Some(cell_width))
+    }
+}
+
+impl<K: Hash + Eq + Clone, T: Eq + Clone> MeshGrouper<K, T> {
+    /// A grouper for tagged meshes, grouping spatially if a `cell_width` is given
+    pub fn new_tagged(max_vertices_per_group: usize, cell_width: Option<N>) -> MeshGrouper<K, T> {
         MeshGrouper {
             groups: Vec::new(),
             group_membership: HashMap::new(),
             max_vertices_per_group,
+            cell_width,
+            optimize_meshes: false,
+            reduce_overdraw: false,
+            bvh_min_triangles: None,
         }
     }
 
-    pub fn update<RemI: IntoIterator<Item = K>, AddI: IntoIterator<Item = (K, Mesh)>>(
+    /// Optimizes every added mesh with `Mesh::optimize` before it is put into its group.
+    /// Members occupy separate ranges of a group's buffers, so this optimizes
+    /// the group meshes while keeping incremental changes possible.
+    pub fn optimizing_meshes(self, reduce_overdraw: bool) -> Self {
+        MeshGrouper {
+            optimize_meshes: true,
+            reduce_overdraw,
+            ..self
+        }
+    }
+
+    /// Keeps a `MeshBvh` for each group with at least `min_triangles`, which `pick`
+    /// uses instead of testing every triangle of the members the ray might hit.
+    /// It is rebuilt whenever the group changes.
+    pub fn with_picking_bvh(self, min_triangles: usize) -> Self {
+        MeshGrouper {
+            bvh_min_triangles: Some(min_triangles),
+            ..self
+        }
+    }
+
+    /// The changes of all groups since the last call, rebuilding BVHs of changed groups
+    fn collect_changes(&mut self) -> Vec<GroupChange<T>> {
+        let bvh_min_triangles = self.bvh_min_triangles;
+
+        self.groups
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(i, group)| {
+                let change = group.get_change_if_changed(i)?;
+                group.bvh = bvh_min_triangles
+                    .filter(|min_triangles| group.mesh.indices.len() / 3 >= *min_triangles)
+                    .map(|_| MeshBvh::new(&group.mesh));
+                Some(change)
+            })
+            .collect()
+    }
+
+    fn cell_of(&self, bounds: &Option<Bounds>) -> Option<CellCoords> {
+        let cell_width = self.cell_width?;
+        let center = bounds.as_ref()?.bounding_box.center();
+        Some(CellCoords(
+            (center.x / cell_width).floor() as i32,
+            (center.y / cell_width).floor() as i32,
+        ))
+    }
+
+    /// Removes and adds meshes, returning incremental changes of all affected groups.
+    ///
+    /// New meshes go into the first group (of their cell, if grouping spatially)
+    /// they fit into, filling gaps left by removed meshes if possible. Removing a mesh
+    /// moves the last meshes of its group into the gap if they fit, so only their
+    /// ranges have to be uploaded again.
+    ///
+    /// Adding a key that is already present replaces its mesh, keeping it in the same group
+    /// if the new mesh still fits there. If a key occurs several times in `to_add`,
+    /// the last mesh wins. Removals are applied before additions, so a key that is
+    /// both removed and added ends up with the added mesh.
+    pub fn update_tagged<RemI: IntoIterator<Item = K>, AddI: IntoIterator<Item = (K, Mesh, T)>>(
         &mut self,
         to_remove: RemI,
         to_add: AddI,
-    ) -> Vec<GroupChange> {
+    ) -> Vec<GroupChange<T>> {
         for key_to_remove in to_remove {
             let group_idx = self.group_membership[&key_to_remove];
             self.groups[group_idx].remove(&key_to_remove);
+            self.group_membership.remove(&key_to_remove);
         }
 
-        for new_member in to_add {
-            let mut current_group_idx = 0;
-            let new_member_n_vertices = new_member.1.vertices.len();
-            let mut to_push = (vec![new_member], new_member_n_vertices);
+        for (key, mut mesh, tag) in to_add {
+            if self.optimize_meshes {
+                mesh.optimize(self.reduce_overdraw);
+            }
 
-            while !to_push.0.is_empty() {
-                // all members that are currently to push will fit in the current group!
-                for (member_key, _) in &to_push.0 {
-                    self.group_membership
-                        .insert(member_key.clone(), current_group_idx);
-                }
+            let (n_vertices, n_indices) = (mesh.vertices.len(), mesh.indices.len());
+            let bounds = mesh.bounds();
+            let cell = self.cell_of(&bounds);
 
-                let found_group = if let Some(group) = self.groups.get_mut(current_group_idx) {
-                    to_push = group.push(to_push.0, to_push.1);
-                    current_group_idx += 1;
-                    true
+            if let Some(&group_idx) = self.group_membership.get(&key) {
+                let group = &mut self.groups[group_idx];
+                if group.accepts(cell, &tag) && group.fits_replacement(&key, n_vertices, n_indices) {
+                    group.replace(key, &mesh, bounds);
+                    continue;
                 } else {
-                    false
-                };
-
-                if !found_group {
-                    let mut new_group = MeshQueue::new(self.max_vertices_per_group);
-                    to_push = new_group.push(to_push.0, to_push.1);
-                    self.groups.push(new_group);
-                    // the rest should always fit in the last new group
-                    assert!(to_push.0.is_empty());
+                    group.remove(&key);
                 }
             }
+
+            let group_idx = match self
+                .groups
+                .iter()
+                .position(|group| group.accepts(cell, &tag) && group.fits(n_vertices, n_indices))
+                .or_else(|| self.groups.iter().position(|group| group.members.is_empty()))
+            {
+                Some(group_idx) => {
+                    self.groups[group_idx].cell = cell;
+                    self.groups[group_idx].tag = tag;
+                    group_idx
+                }
+                None => {
+                    self.groups.push(MeshGroup::new(self.max_vertices_per_group, cell, tag));
+                    self.groups.len() - 1
+                }
+            };
+
+            self.group_membership.insert(key.clone(), group_idx);
+            self.groups[group_idx].push(key, &mesh, bounds);
         }
 
-        self.groups
-            .iter_mut()
+        self.collect_changes()
+    }
+
+    /// Repacks members of sparsely filled groups into fuller groups (with the same tag
+    /// and of the same cell, if grouping spatially), to reduce the number of non-empty groups and draw calls.
+    ///
+    /// At most `vertex_budget` vertices are moved per call (if given), so compaction can be
+    /// spread over several frames by calling this repeatedly until it is finished.
+    pub fn compact(&mut self, vertex_budget: Option<usize>) -> Compaction<T> {
+        let mut remaining_budget = vertex_budget.unwrap_or(usize::MAX);
+        let mut emptied_groups = Vec::new();
+        let mut finished = true;
+
+        // drain the least filled group, if the others have enough space left for it
+        let mut undrainable = vec![false; self.groups.len()];
+
+        'sources: while let Some((source_idx, _)) = self
+            .groups
+            .iter()
             .enumerate()
-            .filter_map(|(i, group)| {
-                group.get_mesh_if_changed().map(|mesh| GroupChange {
-                    group_id: i,
-                    new_group_mesh: mesh,
+            .filter(|(i, group)| !group.members.is_empty() && !undrainable[*i])
+            .min_by_key(|(_, group)| group.n_vertices())
+        {
+            let source_cell = self.groups[source_idx].cell;
+            let source_tag = self.groups[source_idx].tag.clone();
+            let free_space_elsewhere: usize = self
+                .groups
+                .iter()
+                .enumerate()
+                .filter(|(i, group)| {
+                    *i != source_idx
+                        && !group.members.is_empty()
+                        && group.accepts(source_cell, &source_tag)
                 })
+                .map(|(_, group)| group.max_vertices - group.n_vertices())
+                .sum();
+
+            if free_space_elsewhere < self.groups[source_idx].n_vertices() {
+                undrainable[source_idx] = true;
+                continue;
+            }
+
+            while let Some(member) = self.groups[source_idx].members.last() {
+                if member.n_vertices > remaining_budget {
+                    finished = false;
+                    break 'sources;
+                }
+
+                // best fit: the fullest other group that still has space
+                let target_idx = match self
+                    .groups
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, group)| {
+                        *i != source_idx
+                            && !group.members.is_empty()
+                            && group.accepts(source_cell, &source_tag)
+                            && group.fits(member.n_vertices, member.n_indices)
+                    })
+                    .max_by_key(|(_, group)| group.n_vertices())
+                {
+                    Some((target_idx, _)) => target_idx,
+                    None => {
+                        undrainable[source_idx] = true;
+                        continue 'sources;
+                    }
+                };
+
+                let key = member.key.clone();
+                let bounds = member.bounds;
+                let mesh = self.groups[source_idx].member_mesh(member);
+                remaining_budget -= mesh.vertices.len();
+
+                self.groups[source_idx].remove(&key);
+                self.groups[target_idx].push(key.clone(), &mesh, bounds);
+                self.group_membership.insert(key, target_idx);
+            }
+
+            emptied_groups.push(source_idx);
+        }
+
+        Compaction {
+            changes: self.collect_changes(),
+            emptied_groups,
+            finished,
+        }
+    }
+
+    /// The full current mesh of a group, e.g. to recreate its buffers from scratch.
+    /// Gaps left by removed meshes show up as unused vertices and degenerate triangles.
+    pub fn group_mesh(&self, group_id: usize) -> Option<&Mesh> {
+        self.groups.get(group_id).map(|group| &group.mesh)
+    }
+
+    /// The closest member hit by the ray, together with the hit.
+    /// The hit's triangle index refers to the member's own mesh.
+    ///
+    /// Members are tested in the order the ray enters their bounds. Groups with a BVH
+    /// (see `with_picking_bvh`) are tested as a whole instead.
+    pub fn pick(&self, ray: &Ray) -> Option<(K, RayHit)> {
+        let mut candidates = self
+            .groups
+            .iter()
+            .flat_map(|group| {
+                if group.bvh.is_some() {
+                    group.bounds().map(|bounds| (bounds, group, None)).into_iter().collect()
+                } else {
+                    group
+                        .members
+                        .iter()
+                        .filter_map(|member| Some((member.bounds?, group, Some(member))))
+                        .collect::<Vec<_>>()
+                }
             })
+            .filter_map(|(bounds, group, member)| {
+                let entry = ray.intersect_box(&bounds.bounding_box)?;
+                Some((entry, group, member))
+            })
+            .collect::<Vec<_>>();
+
+        candidates.sort_by(|(entry_a, _, _), (entry_b, _, _)| {
+            entry_a.partial_cmp(entry_b).unwrap_or(::std::cmp::Ordering::Equal)
+        });
+
+        let mut closest: Option<(&Member<K>, RayHit)> = None;
+
+        for (entry, group, member) in candidates {
+            let closest_distance = closest.map(|(_, closest_hit)| closest_hit.distance);
+
+            if closest_distance.map(|distance| entry > distance).unwrap_or(false) {
+                break;
+            }
+
+            let max_distance = closest_distance.unwrap_or(N::INFINITY);
+
+            let member_hit = match (member, &group.bvh) {
+                (Some(member), _) => {
+                    let first_triangle = member.index_offset / 3;
+                    let member_triangles = first_triangle..(first_triangle + member.n_indices / 3);
+                    closest_hit(&group.mesh, ray, member_triangles, max_distance).map(|hit| (member, hit))
+                }
+                (None, Some(bvh)) => bvh
+                    .closest_hit(&group.mesh, ray, max_distance)
+                    .and_then(|hit| Some((group.member_of_triangle(hit.triangle_index)?, hit))),
+                (None, None) => None,
+            };
+
+            if let Some((member, mut hit)) = member_hit {
+                hit.triangle_index -= member.index_offset / 3;
+                closest = Some((member, hit));
+            }
+        }
+
+        closest.map(|(member, hit)| (member.key.clone(), hit))
+    }
+}
+
+/// One `MeshGrouper` per level of detail, each added mesh is simplified
+/// into an LOD chain and every level is grouped separately, so each
+/// level can be drawn on its own.
+pub struct LodMeshGrouper<K: Hash + Eq + Clone, T: Eq + Clone = ()> {
+    levels: Vec<MeshGrouper<K, T>>,
+    triangle_ratios: Vec<N>,
+}
+
+impl<K: Hash + Eq + Clone> LodMeshGrouper<K> {
+    /// `triangle_ratios` are the ratios of the original number of triangles
+    /// kept at each level, see `Mesh::lod_chain`
+    pub fn new(max_vertices_per_group: usize, triangle_ratios: Vec<N>) -> LodMeshGrouper<K> {
+        LodMeshGrouper::new_tagged(max_vertices_per_group, None, triangle_ratios)
+    }
+}
+
+impl<K: Hash + Eq + Clone, T: Eq + Clone + Default> LodMeshGrouper<K, T> {
+    /// Like `update_tagged`, giving all added meshes the default tag
+    pub fn update<RemI: IntoIterator<Item = K>, AddI: IntoIterator<Item = (K, Mesh)>>(
+        &mut self,
+        to_remove: RemI,
+        to_add: AddI,
+    ) -> Vec<Vec<GroupChange<T>>> {
+        self.update_tagged(
+            to_remove,
+            to_add.into_iter().map(|(key, mesh)| (key, mesh, T::default())),
+        )
+    }
+}
+
+impl<K: Hash + Eq + Clone, T: Eq + Clone> LodMeshGrouper<K, T> {
+    /// A grouper for tagged meshes, grouping spatially if a `cell_width` is given
+    pub fn new_tagged(
+        max_vertices_per_group: usize,
+        cell_width: Option<N>,
+        triangle_ratios: Vec<N>,
+    ) -> LodMeshGrouper<K, T> {
+        LodMeshGrouper {
+            levels: triangle_ratios
+                .iter()
+                .map(|_| MeshGrouper::new_tagged(max_vertices_per_group, cell_width))
+                .collect(),
+            triangle_ratios,
+        }
+    }
+
+    /// Like `MeshGrouper::update_tagged`, returning the group changes of each level
+    pub fn update_tagged<RemI: IntoIterator<Item = K>, AddI: IntoIterator<Item = (K, Mesh, T)>>(
+        &mut self,
+        to_remove: RemI,
+        to_add: AddI,
+    ) -> Vec<Vec<GroupChange<T>>> {
+        let to_remove = to_remove.into_iter().collect::<Vec<_>>();
+        let mut to_add_per_level = self.levels.iter().map(|_| Vec::new()).collect::<Vec<_>>();
+
+        for (key, mesh, tag) in to_add {
+            for (level_to_add, level_mesh) in to_add_per_level
+                .iter_mut()
+                .zip(mesh.lod_chain(&self.triangle_ratios))
+            {
+                level_to_add.push((key.clone(), level_mesh, tag.clone()));
+            }
+        }
+
+        self.levels
+            .iter_mut()
+            .zip(to_add_per_level)
+            .map(|(level, to_add)| level.update_tagged(to_remove.iter().cloned(), to_add))
             .collect()
     }
-}
\ No newline at end of file
+
+    /// The grouper of one level, e.g. to compact it or to get group meshes
+    pub fn level(&self, level: usize) -> &MeshGrouper<K, T> {
+        &self.levels[level]
+    }
+
+    pub fn level_mut(&mut self, level: usize) -> &mut MeshGrouper<K, T> {
+        &mut self.levels[level]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use descartes::{P3, V3};
+
+    fn quads(n_quads: usize, x: N) -> Mesh {
+        let vertices = (0..n_quads)
+            .flat_map(|i| {
+                let y = i as N;
+                vec![[x, y, 0.0], [x + 1.0, y, 0.0], [x + 1.0, y + 1.0, 0.0], [x, y + 1.0, 0.0]]
+            })
+            .map(|position| Vertex { position })
+            .collect::<Vec<_>>();
+        let indices = (0..n_quads as u16)
+            .flat_map(|i| vec![0, 1, 2, 0, 2, 3].into_iter().map(move |index| i * 4 + index))
+            .collect();
+        Mesh::new(vertices, indices)
+    }
+
+    /// Copies of the group buffers that are only updated through `GroupChange`s, like GPU buffers
+    #[derive(Default)]
+    struct PatchedBuffers {
+        groups: HashMap<usize, (Vec<[N; 3]>, Vec<u16>)>,
+    }
+
+    impl PatchedBuffers {
+        fn apply<T>(&mut self, changes: &[GroupChange<T>]) {
+            for change in changes {
+                let (vertices, indices) = self.groups.entry(change.group_id).or_default();
+                vertices.resize(change.n_vertices, [N::NAN; 3]);
+                indices.resize(change.n_indices, u16::MAX);
+
+                for (offset, patch) in &change.vertex_patches {
+                    for (i, vertex) in patch.iter().enumerate() {
+                        vertices[offset + i] = vertex.position;
+                    }
+                }
+                for (offset, patch) in &change.index_patches {
+                    indices[*offset..offset + patch.len()].copy_from_slice(patch);
+                }
+            }
+        }
+
+        fn assert_matches<K: Hash + Eq + Clone, T: Eq + Clone>(&self, grouper: &MeshGrouper<K, T>) {
+            for (group_id, (vertices, indices)) in &self.groups {
+                let mesh = grouper.group_mesh(*group_id).unwrap();
+                assert_eq!(indices[..], mesh.indices[..]);
+                assert_eq!(vertices.len(), mesh.vertices.len());
+                // vertices in gaps are never drawn, so they don't have to match
+                for index in indices {
+                    assert_eq!(vertices[*index as usize], mesh.vertices[*index as usize].position);
+                }
+            }
+        }
+    }
+
+    fn n_patched_vertices<T>(changes: &[GroupChange<T>]) -> usize {
+        changes
+            .iter()
+            .flat_map(|change| change.vertex_patches.iter())
+            .map(|(_, patch)| patch.len())
+            .sum()
+    }
+
+    #[test]
+    fn patches_reproduce_group_buffers() {
+        let mut grouper = MeshGrouper::new(1000);
+        let mut buffers = PatchedBuffers::default();
+
+        let changes = grouper.update(vec![], (0..40).map(|key| (key, quads(1 + key % 4, key as N))));
+        buffers.apply(&changes);
+        buffers.assert_matches(&grouper);
+
+        let steps = vec![
+            (vec![3, 17, 39], vec![]),
+            (vec![], vec![(5, 1), (6, 4), (100, 2)]),
+            (vec![0, 1, 2], vec![(101, 3), (102, 1)]),
+            (vec![10, 11, 12, 13, 14], vec![(20, 2), (21, 4), (103, 3)]),
+            ((15..39).filter(|key| *key != 17 && *key != 20 && *key != 21).collect::<Vec<_>>(), vec![]),
+        ];
+
+        for (to_remove, to_add) in steps {
+            let changes = grouper.update(
+                to_remove,
+                to_add.into_iter().map(|(key, n_quads)| (key, quads(n_quads, key as N))),
+            );
+            buffers.apply(&changes);
+            buffers.assert_matches(&grouper);
+        }
+    }
+
+    #[test]
+    fn removal_only_reports_moved_meshes() {
+        let mut grouper = MeshGrouper::new(60_000);
+        grouper.update(vec![], (0..150).map(|key| (key, quads(100, key as N))));
+
+        let changes = grouper.update(vec![7], vec![]);
+        assert_eq!(n_patched_vertices(&changes), 400);
+        assert_eq!(changes[0].n_vertices, 59_600);
+    }
+
+    #[test]
+    fn picking_with_bvh_finds_the_same_members() {
+        let meshes = (0..30).map(|key| (key, quads(1 + key % 3, 2.0 * key as N))).collect::<Vec<_>>();
+        let mut grouper = MeshGrouper::new(100);
+        let mut bvh_grouper = MeshGrouper::new(100).with_picking_bvh(1);
+        grouper.update(vec![], meshes.clone());
+        bvh_grouper.update(vec![], meshes);
+        grouper.update(vec![4, 9], vec![]);
+        bvh_grouper.update(vec![4, 9], vec![]);
+
+        let mut n_hits = 0;
+        for i in 0..130 {
+            let ray = Ray::new(P3::new(i as N * 0.5 + 0.13, 0.7, 5.0), V3::new(0.1, 0.0, -1.0));
+            let hit = grouper.pick(&ray).map(|(key, hit)| (key, hit.triangle_index));
+            let bvh_hit = bvh_grouper.pick(&ray).map(|(key, hit)| (key, hit.triangle_index));
+            assert_eq!(hit, bvh_hit);
+            n_hits += hit.is_some() as usize;
+        }
+        assert!(n_hits > 50);
+    }
+
+    #[test]
+    fn replacements_are_reported_separately() {
+        let mut grouper = MeshGrouper::new(60_000);
+        let mut buffers = PatchedBuffers::default();
+        buffers.apply(&grouper.update(vec![], (0..150).map(|key| (key, quads(100, key as N)))));
+
+        let changes = grouper.update(vec![], vec![(0, quads(100, -1.0)), (149, quads(100, -2.0))]);
+        assert_eq!(n_patched_vertices(&changes), 800);
+        assert_eq!(changes[0].vertex_patches.len(), 2);
+        buffers.apply(&changes);
+        buffers.assert_matches(&grouper);
+    }
+}
@@ -0,0 +1,199 @@
+use descartes::{N, P3, V3};
+use crate::mesh::{Mesh, triangle_normal, triangle_points};
+
+// Tom Forsyth, "Linear-Speed Vertex Cache Optimisation" (2006)
+const CACHE_SIZE: usize = 32;
+const CACHE_DECAY_POWER: N = 1.5;
+const LAST_TRIANGLE_SCORE: N = 0.75;
+const VALENCE_BOOST_SCALE: N = 2.0;
+const VALENCE_BOOST_POWER: N = 0.5;
+
+// the FIFO cache size assumed when splitting triangles into clusters for overdraw sorting
+const CLUSTER_CACHE_SIZE: usize = 16;
+
+fn vertex_score(cache_position: Option<usize>, remaining_valence: usize) -> N {
+    if remaining_valence == 0 {
+        return -1.0;
+    }
+
+    let cache_score = match cache_position {
+        Some(position) if position < 3 => LAST_TRIANGLE_SCORE,
+        Some(position) => {
+            let scaler = 1.0 / (CACHE_SIZE - 3) as N;
+            (1.0 - (position - 3) as N * scaler).powf(CACHE_DECAY_POWER)
+        }
+        None => 0.0,
+    };
+
+    cache_score + VALENCE_BOOST_SCALE * (remaining_valence as N).powf(-VALENCE_BOOST_POWER)
+}
+
+impl Mesh {
+    /// Reorders triangles so that vertices are reused while they're still
+    /// in the GPU's post-transform cache, using Forsyth's algorithm
+    pub fn optimize_vertex_cache(&mut self) {
+        let n_vertices = self.vertices.len();
+        let n_triangles = self.indices.len() / 3;
+
+        let mut vertex_triangles = vec![Vec::new(); n_vertices];
+        for (triangle_index, triangle) in self.indices.chunks(3).enumerate() {
+            for index in triangle {
+                vertex_triangles[*index as usize].push(triangle_index);
+            }
+        }
+
+        let mut remaining_valence = vertex_triangles.iter().map(Vec::len).collect::<Vec<_>>();
+        let mut vertex_scores = (0..n_vertices)
+            .map(|vertex| vertex_score(None, remaining_valence[vertex]))
+            .collect::<Vec<_>>();
+
+        let triangle_score = |triangle_index: usize, vertex_scores: &[N], indices: &[u16]| {
+            indices[3 * triangle_index..3 * triangle_index + 3]
+                .iter()
+                .map(|index| vertex_scores[*index as usize])
+                .sum::<N>()
+        };
+
+        let mut emitted = vec![false; n_triangles];
+        let mut cache = Vec::<u16>::with_capacity(CACHE_SIZE + 3);
+        let mut optimized_indices = Vec::with_capacity(self.indices.len());
+        // where to continue looking for a triangle if no cached vertex has one left
+        let mut next_unemitted = 0;
+
+        while optimized_indices.len() < self.indices.len() {
+            let best_cached = cache
+                .iter()
+                .flat_map(|vertex| vertex_triangles[*vertex as usize].iter())
+                .filter(|triangle_index| !emitted[**triangle_index])
+                .map(|triangle_index| {
+                    (*triangle_index, triangle_score(*triangle_index, &vertex_scores, &self.indices))
+                })
+                .fold(None, |best: Option<(usize, N)>, candidate| match best {
+                    Some(best) if best.1 >= candidate.1 => Some(best),
+                    _ => Some(candidate),
+                });
+
+            let best_triangle = match best_cached {
+                Some((triangle_index, _)) => triangle_index,
+                None => {
+                    while emitted[next_unemitted] {
+                        next_unemitted += 1;
+                    }
+                    next_unemitted
+                }
+            };
+
+            emitted[best_triangle] = true;
+            let triangle = [
+                self.indices[3 * best_triangle],
+                self.indices[3 * best_triangle + 1],
+                self.indices[3 * best_triangle + 2],
+            ];
+            optimized_indices.extend_from_slice(&triangle);
+
+            for vertex in triangle.iter() {
+                remaining_valence[*vertex as usize] -= 1;
+                cache.retain(|cached| cached != vertex);
+            }
+            for vertex in triangle.iter().rev() {
+                cache.insert(0, *vertex);
+            }
+
+            for vertex in cache.drain(CACHE_SIZE.min(cache.len())..) {
+                vertex_scores[vertex as usize] = vertex_score(None, remaining_valence[vertex as usize]);
+            }
+
+            for (position, vertex) in cache.iter().enumerate() {
+                vertex_scores[*vertex as usize] =
+                    vertex_score(Some(position), remaining_valence[*vertex as usize]);
+            }
+        }
+
+        self.indices = optimized_indices.into();
+    }
+
+    /// Reorders clusters of triangles so that outward-facing ones are drawn first
+    /// and occlude the ones behind them, reducing overdraw from any direction.
+    /// Clusters are split where the vertex cache would be cold anyway,
+    /// so call this after `optimize_vertex_cache`.
+    pub fn optimize_overdraw(&mut self) {
+        let mut clusters = Vec::<Vec<u16>>::new();
+        let mut cache = Vec::<u16>::with_capacity(CLUSTER_CACHE_SIZE + 3);
+
+        for triangle in self.indices.chunks(3) {
+            let misses = triangle.iter().filter(|index| !cache.contains(index)).count();
+
+            if misses == 3 || clusters.is_empty() {
+                clusters.push(Vec::new());
+            }
+            clusters.last_mut().unwrap().extend_from_slice(triangle);
+
+            for index in triangle {
+                if !cache.contains(index) {
+                    cache.insert(0, *index);
+                }
+            }
+            cache.truncate(CLUSTER_CACHE_SIZE);
+        }
+
+        let mesh_center = match self.bounds() {
+            Some(bounds) => bounds.bounding_box.center(),
+            None => return,
+        };
+
+        let mut sorted_clusters = clusters
+            .into_iter()
+            .map(|cluster| {
+                let mut area_weighted_center = V3::new(0.0, 0.0, 0.0);
+                let mut area_weighted_normal = V3::new(0.0, 0.0, 0.0);
+                let mut total_area = 0.0;
+
+                for triangle in cluster.chunks(3) {
+                    let [a, b, c] = triangle_points(self, triangle);
+                    let normal = triangle_normal(a, b, c);
+                    let area = normal.norm() / 2.0;
+                    area_weighted_center += (a.coords + b.coords + c.coords) / 3.0 * area;
+                    area_weighted_normal += normal;
+                    total_area += area;
+                }
+
+                let outwardness = if total_area > 0.0 && area_weighted_normal.norm() > 0.0 {
+                    let center = P3::from(area_weighted_center / total_area);
+                    (center - mesh_center).dot(&area_weighted_normal.normalize())
+                } else {
+                    N::NEG_INFINITY
+                };
+
+                (outwardness, cluster)
+            })
+            .collect::<Vec<_>>();
+
+        // stable, so ties keep their cache-friendly order
+        sorted_clusters.sort_by(|(a, _), (b, _)| {
+            b.partial_cmp(a).unwrap_or(::std::cmp::Ordering::Equal)
+        });
+
+        self.indices = sorted_clusters
+            .into_iter()
+            .flat_map(|(_, cluster)| cluster)
+            .collect::<Vec<_>>()
+            .into();
+    }
+
+    /// Reorders vertices by their first use, so vertex fetches are mostly sequential.
+    /// Unused vertices are dropped.
+    pub fn optimize_vertex_fetch(&mut self) {
+        self.remove_unused_vertices();
+    }
+
+    /// Optimizes the triangle order for the vertex cache (and optionally for overdraw),
+    /// then the vertex order for fetch locality. Only the order changes,
+    /// the mesh looks the same.
+    pub fn optimize(&mut self, reduce_overdraw: bool) {
+        self.optimize_vertex_cache();
+        if reduce_overdraw {
+            self.optimize_overdraw();
+        }
+        self.optimize_vertex_fetch();
+    }
+}
@@ -0,0 +1,107 @@
+use descartes::N;
+
+/// A small pseudo random number generator (SplitMix64) that only uses integer
+/// arithmetic, so it yields the same numbers for the same seed on every platform
+#[derive(Clone)]
+pub(crate) struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    /// A generator for one of many independent streams, like one per grid cell
+    pub fn for_stream(seed: u64, stream: u64) -> Rng {
+        Rng::new(Rng::new(seed ^ Rng::new(stream).next_u64()).next_u64())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniformly distributed in `[0, 1)`, exactly representable as `N`
+    pub fn next_unit(&mut self) -> N {
+        (self.next_u64() >> 40) as N / (1u64 << 24) as N
+    }
+
+    /// Uniformly distributed in `[min, max)`
+    pub fn in_range(&mut self, min: N, max: N) -> N {
+        min + (max - min) * self.next_unit()
+    }
+}
+
+/// Deterministic variation for procedural geometry, like roof heights, window spacing
+/// or colors. The same seed (e.g. a lot ID) always yields the same values, bit-identical
+/// on every platform, since only integer arithmetic and basic float operations are used.
+#[derive(Clone)]
+pub struct Variation {
+    seed: u64,
+    rng: Rng,
+}
+
+impl Variation {
+    pub fn new(seed: u64) -> Variation {
+        Variation {
+            seed,
+            rng: Rng::new(seed),
+        }
+    }
+
+    /// An independent variation for a part, like one floor of a building,
+    /// which stays the same no matter how many values are drawn elsewhere
+    pub fn part(&self, key: u64) -> Variation {
+        Variation::new(Rng::for_stream(self.seed, key).next_u64())
+    }
+
+    /// Uniformly distributed in `[0, 1)`
+    pub fn unit(&mut self) -> N {
+        self.rng.next_unit()
+    }
+
+    /// Uniformly distributed in `[min, max)`
+    pub fn range(&mut self, min: N, max: N) -> N {
+        self.rng.in_range(min, max)
+    }
+
+    /// Uniformly distributed in `[value - max_deviation, value + max_deviation)`
+    pub fn around(&mut self, value: N, max_deviation: N) -> N {
+        self.rng.in_range(value - max_deviation, value + max_deviation)
+    }
+
+    /// Uniformly distributed in `min..=max`
+    pub fn integer(&mut self, min: i64, max: i64) -> i64 {
+        if max <= min {
+            return min;
+        }
+        min + (self.rng.next_u64() % (max - min + 1) as u64) as i64
+    }
+
+    /// `true` with the given probability
+    pub fn chance(&mut self, probability: N) -> bool {
+        self.unit() < probability
+    }
+
+    /// One of the options, `None` if there are none
+    pub fn pick<'a, T>(&mut self, options: &'a [T]) -> Option<&'a T> {
+        if options.is_empty() {
+            None
+        } else {
+            options.get((self.rng.next_u64() % options.len() as u64) as usize)
+        }
+    }
+
+    /// Each channel moved by up to `max_deviation`, staying within `[0, 1]`
+    pub fn color(&mut self, base: [f32; 3], max_deviation: N) -> [f32; 3] {
+        let mut varied = base;
+        for channel in varied.iter_mut() {
+            *channel = self.around(*channel, max_deviation).clamp(0.0, 1.0);
+        }
+        varied
+    }
+}
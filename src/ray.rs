@@ -0,0 +1,256 @@
+use descartes::{N, P3, V3, Iso3, Persp3};
+use crate::bounds::BoundingBox3;
+use crate::mesh::{Mesh, triangle_points};
+
+#[derive(Copy, Clone, Debug)]
+pub struct Ray {
+    pub origin: P3,
+    /// Normalized, so hit distances are in world units
+    pub direction: V3,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct RayHit {
+    pub distance: N,
+    pub point: P3,
+    /// Index of the hit triangle, i.e. its first index is at `3 * triangle_index`
+    pub triangle_index: usize,
+}
+
+impl Ray {
+    pub fn new(origin: P3, direction: V3) -> Ray {
+        Ray {
+            origin,
+            direction: direction.normalize(),
+        }
+    }
+
+    /// The ray through a point on screen, given in normalized device coordinates
+    /// (-1 to 1, y up), for a camera whose `view` transforms world into eye coordinates
+    pub fn from_screen(perspective: &Persp3, view: &Iso3, screen_x: N, screen_y: N) -> Ray {
+        let eye_to_world = view.inverse();
+        let near = eye_to_world * perspective.unproject_point(&P3::new(screen_x, screen_y, -1.0));
+        let far = eye_to_world * perspective.unproject_point(&P3::new(screen_x, screen_y, 1.0));
+        Ray::new(near, far - near)
+    }
+
+    pub fn at(&self, distance: N) -> P3 {
+        self.origin + self.direction * distance
+    }
+
+    /// The distance at which the ray enters the box (0 if it starts inside it)
+    pub fn intersect_box(&self, bounding_box: &BoundingBox3) -> Option<N> {
+        let mut entry: N = 0.0;
+        let mut exit = N::INFINITY;
+
+        for axis in 0..3 {
+            let inverse_direction = 1.0 / self.direction[axis];
+            let to_min = (bounding_box.min[axis] - self.origin[axis]) * inverse_direction;
+            let to_max = (bounding_box.max[axis] - self.origin[axis]) * inverse_direction;
+
+            // NaN (ray parallel to and on a slab boundary) is ignored by min/max
+            entry = entry.max(to_min.min(to_max));
+            exit = exit.min(to_min.max(to_max));
+        }
+
+        if entry <= exit {
+            Some(entry)
+        } else {
+            None
+        }
+    }
+
+    /// Möller–Trumbore, hitting triangles from both sides
+    pub fn intersect_triangle(&self, a: P3, b: P3, c: P3) -> Option<N> {
+        let edge_1 = b - a;
+        let edge_2 = c - a;
+        let p = self.direction.cross(&edge_2);
+        let determinant = edge_1.dot(&p);
+
+        if determinant.abs() < N::EPSILON {
+            return None;
+        }
+
+        let inverse_determinant = 1.0 / determinant;
+        let to_origin = self.origin - a;
+        let u = to_origin.dot(&p) * inverse_determinant;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let q = to_origin.cross(&edge_1);
+        let v = self.direction.dot(&q) * inverse_determinant;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let distance = edge_2.dot(&q) * inverse_determinant;
+        if distance >= 0.0 {
+            Some(distance)
+        } else {
+            None
+        }
+    }
+}
+
+impl Mesh {
+    /// The closest hit of the ray, testing every triangle.
+    /// For large meshes that are hit often, build a `MeshBvh` instead.
+    pub fn intersect_ray(&self, ray: &Ray) -> Option<RayHit> {
+        closest_hit(self, ray, 0..self.indices.len() / 3, N::INFINITY)
+    }
+}
+
+pub(crate) fn closest_hit<I: IntoIterator<Item = usize>>(
+    mesh: &Mesh,
+    ray: &Ray,
+    triangle_indices: I,
+    max_distance: N,
+) -> Option<RayHit> {
+    let mut closest: Option<RayHit> = None;
+
+    for triangle_index in triangle_indices {
+        let [a, b, c] = triangle_points(mesh, &mesh.indices[3 * triangle_index..3 * triangle_index + 3]);
+        if let Some(distance) = ray.intersect_triangle(a, b, c) {
+            let closest_distance = closest.map(|hit| hit.distance).unwrap_or(max_distance);
+            if distance < closest_distance {
+                closest = Some(RayHit {
+                    distance,
+                    point: ray.at(distance),
+                    triangle_index,
+                });
+            }
+        }
+    }
+
+    closest
+}
+
+enum BvhNodeContent {
+    Leaf { first: usize, count: usize },
+    Inner { left: usize, right: usize },
+}
+
+struct BvhNode {
+    bounding_box: BoundingBox3,
+    content: BvhNodeContent,
+}
+
+const MAX_TRIANGLES_PER_LEAF: usize = 4;
+
+/// A bounding volume hierarchy over the triangles of a mesh, to speed up ray casts.
+/// It doesn't hold on to the mesh, but has to be queried with the same, unchanged mesh.
+pub struct MeshBvh {
+    nodes: Vec<BvhNode>,
+    triangle_indices: Vec<usize>,
+}
+
+impl MeshBvh {
+    pub fn new(mesh: &Mesh) -> MeshBvh {
+        let n_triangles = mesh.indices.len() / 3;
+        let triangle_boxes = (0..n_triangles)
+            .map(|triangle_index| {
+                let [a, b, c] = triangle_points(mesh, &mesh.indices[3 * triangle_index..3 * triangle_index + 3]);
+                BoundingBox3::point(a)
+                    .extended_by(BoundingBox3::point(b))
+                    .extended_by(BoundingBox3::point(c))
+            })
+            .collect::<Vec<_>>();
+
+        let mut bvh = MeshBvh {
+            nodes: Vec::new(),
+            triangle_indices: (0..n_triangles).collect(),
+        };
+
+        if n_triangles > 0 {
+            bvh.build_node(&triangle_boxes, 0, n_triangles);
+        }
+
+        bvh
+    }
+
+    fn build_node(&mut self, triangle_boxes: &[BoundingBox3], first: usize, count: usize) -> usize {
+        let members = &mut self.triangle_indices[first..first + count];
+        let bounding_box = members
+            .iter()
+            .skip(1)
+            .fold(triangle_boxes[members[0]], |bbox, triangle_index| {
+                bbox.extended_by(triangle_boxes[*triangle_index])
+            });
+
+        let node_index = self.nodes.len();
+        self.nodes.push(BvhNode {
+            bounding_box,
+            content: BvhNodeContent::Leaf { first, count },
+        });
+
+        if count > MAX_TRIANGLES_PER_LEAF {
+            // split at the median along the longest axis
+            let extent = bounding_box.max - bounding_box.min;
+            let axis = if extent.x >= extent.y && extent.x >= extent.z {
+                0
+            } else if extent.y >= extent.z {
+                1
+            } else {
+                2
+            };
+            let center_along_axis =
+                |triangle_index: &usize| triangle_boxes[*triangle_index].center()[axis];
+            members.sort_by(|a, b| {
+                center_along_axis(a)
+                    .partial_cmp(&center_along_axis(b))
+                    .unwrap_or(::std::cmp::Ordering::Equal)
+            });
+
+            let left_count = count / 2;
+            let left = self.build_node(triangle_boxes, first, left_count);
+            let right = self.build_node(triangle_boxes, first + left_count, count - left_count);
+            self.nodes[node_index].content = BvhNodeContent::Inner { left, right };
+        }
+
+        node_index
+    }
+
+    pub fn intersect_ray(&self, mesh: &Mesh, ray: &Ray) -> Option<RayHit> {
+        self.closest_hit(mesh, ray, N::INFINITY)
+    }
+
+    /// The closest hit that is nearer than `max_distance`
+    pub(crate) fn closest_hit(&self, mesh: &Mesh, ray: &Ray, max_distance: N) -> Option<RayHit> {
+        let mut closest: Option<RayHit> = None;
+        let mut stack = Vec::new();
+
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            let closest_distance = closest.map(|hit| hit.distance).unwrap_or(max_distance);
+
+            match ray.intersect_box(&node.bounding_box) {
+                Some(entry) if entry <= closest_distance => {}
+                _ => continue,
+            }
+
+            match node.content {
+                BvhNodeContent::Leaf { first, count } => {
+                    if let Some(hit) = closest_hit(
+                        mesh,
+                        ray,
+                        self.triangle_indices[first..first + count].iter().cloned(),
+                        closest_distance,
+                    ) {
+                        closest = Some(hit);
+                    }
+                }
+                BvhNodeContent::Inner { left, right } => {
+                    stack.push(left);
+                    stack.push(right);
+                }
+            }
+        }
+
+        closest
+    }
+}
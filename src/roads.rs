@@ -0,0 +1,280 @@
+use descartes::{N, P2, V2, LinePath, ClosedLinePath, Area, WithUniqueOrthogonal};
+use crate::mesh::Mesh;
+
+// road ends closer than this meet at the same junction
+const JUNCTION_EPSILON: N = 0.1;
+const CURB_SEGMENTS: usize = 6;
+// square corners between arms that are closer than about 60° are left out
+const MAX_SQUARE_CORNER_COS: N = 0.5;
+
+struct Road {
+    centerline: LinePath,
+    width: N,
+}
+
+/// One road end at a junction
+#[derive(Copy, Clone)]
+struct Arm {
+    road: usize,
+    at_start: bool,
+    // pointing away from the junction
+    direction: V2,
+    trim: N,
+}
+
+struct Junction {
+    // sorted counter-clockwise
+    arms: Vec<Arm>,
+}
+
+/// Roads given by their centerlines and widths, meeting at junctions where
+/// the ends of their centerlines coincide.
+///
+/// Roads are trimmed back at junctions and the junctions filled with a polygon
+/// with rounded curb corners, so roads don't overlap and z-fight.
+pub struct RoadNetwork {
+    roads: Vec<Road>,
+    pub curb_radius: N,
+    pub z: N,
+}
+
+impl RoadNetwork {
+    pub fn new(curb_radius: N, z: N) -> RoadNetwork {
+        RoadNetwork {
+            roads: Vec::new(),
+            curb_radius,
+            z,
+        }
+    }
+
+    /// Returns the id of the road, which is its index in `road_meshes`
+    pub fn add_road(&mut self, centerline: LinePath, width: N) -> usize {
+        self.roads.push(Road { centerline, width });
+        self.roads.len() - 1
+    }
+
+    fn end_of(&self, arm: &Arm) -> P2 {
+        let centerline = &self.roads[arm.road].centerline;
+        if arm.at_start {
+            centerline.start()
+        } else {
+            centerline.end()
+        }
+    }
+
+    fn junctions(&self) -> Vec<Junction> {
+        let mut junctions = Vec::<(P2, Vec<Arm>)>::new();
+
+        for (road_id, road) in self.roads.iter().enumerate() {
+            let ends = [
+                (true, road.centerline.start(), road.centerline.start_direction()),
+                (false, road.centerline.end(), -road.centerline.end_direction()),
+            ];
+
+            for &(at_start, position, direction) in ends.iter() {
+                let arm = Arm {
+                    road: road_id,
+                    at_start,
+                    direction,
+                    trim: 0.0,
+                };
+                match junctions
+                    .iter_mut()
+                    .find(|(center, _)| (center - position).norm() < JUNCTION_EPSILON)
+                {
+                    Some((_, arms)) => arms.push(arm),
+                    None => junctions.push((position, vec![arm])),
+                }
+            }
+        }
+
+        junctions
+            .into_iter()
+            .filter(|(_, arms)| arms.len() >= 2)
+            .map(|(_, mut arms)| {
+                let angle = |arm: &Arm| arm.direction.y.atan2(arm.direction.x);
+                arms.sort_by(|a, b| angle(a).partial_cmp(&angle(b)).unwrap_or(::std::cmp::Ordering::Equal));
+
+                let n_arms = arms.len();
+                let trims = (0..n_arms)
+                    .map(|i| {
+                        let arm = &arms[i];
+                        let half_width = self.roads[arm.road].width / 2.0;
+                        let max_trim = 3.0 * self.roads[arm.road].width;
+
+                        // the distance at which this arm's edge meets the neighbor's edge,
+                        // plus room for the rounded curb. Only edges on the convex side of
+                        // the gap between the arms meet, the others end in a square corner.
+                        let curb_distance = |neighbor: &Arm, sin: N| {
+                            let neighbor_half_width = self.roads[neighbor.road].width / 2.0;
+                            let cos = arm.direction.dot(&neighbor.direction).clamp(-1.0, 1.0);
+                            if sin < 0.01 {
+                                0.0
+                            } else {
+                                let meeting_distance = ((neighbor_half_width + half_width * cos) / sin).max(0.0);
+                                let tangent_length = self.curb_radius * (1.0 + cos) / sin;
+                                (meeting_distance + tangent_length).min(max_trim)
+                            }
+                        };
+
+                        let previous = &arms[(i + n_arms - 1) % n_arms];
+                        let next = &arms[(i + 1) % n_arms];
+                        curb_distance(previous, cross(previous.direction, arm.direction))
+                            .max(curb_distance(next, cross(arm.direction, next.direction)))
+                    })
+                    .collect::<Vec<_>>();
+
+                for (arm, trim) in arms.iter_mut().zip(trims) {
+                    arm.trim = trim;
+                }
+
+                Junction { arms }
+            })
+            .collect()
+    }
+
+    /// The trimmed centerline of each road, `None` if nothing is left of it
+    fn trimmed_centerlines(&self, junctions: &[Junction]) -> Vec<Option<LinePath>> {
+        let mut trims = vec![(0.0, 0.0); self.roads.len()];
+
+        for arm in junctions.iter().flat_map(|junction| junction.arms.iter()) {
+            if arm.at_start {
+                trims[arm.road].0 = arm.trim;
+            } else {
+                trims[arm.road].1 = arm.trim;
+            }
+        }
+
+        self.roads
+            .iter()
+            .zip(trims)
+            .map(|(road, (trim_start, trim_end))| {
+                let length = road.centerline.length();
+                if trim_start + trim_end >= length {
+                    None
+                } else {
+                    road.centerline.subsection(trim_start, length - trim_end)
+                }
+            })
+            .collect()
+    }
+
+    /// The band of each road (in the order they were added), trimmed at junctions.
+    /// Roads that are completely covered by junctions get an empty mesh.
+    pub fn road_meshes(&self) -> Vec<Mesh> {
+        self.trimmed_centerlines(&self.junctions())
+            .into_iter()
+            .zip(self.roads.iter())
+            .map(|(trimmed, road)| {
+                trimmed
+                    .map(|centerline| Mesh::from_path_as_band(&centerline, road.width, self.z))
+                    .unwrap_or_else(Mesh::empty)
+            })
+            .collect()
+    }
+
+    /// The filled polygon of each junction where two or more roads meet
+    pub fn junction_meshes(&self) -> Vec<Mesh> {
+        let junctions = self.junctions();
+        let trimmed_centerlines = self.trimmed_centerlines(&junctions);
+
+        junctions
+            .iter()
+            .filter_map(|junction| {
+                // the left and right corner of each arm's trimmed end, seen from the junction
+                let corners = junction
+                    .arms
+                    .iter()
+                    .map(|arm| {
+                        let half_width = self.roads[arm.road].width / 2.0;
+                        let (position, direction) = match trimmed_centerlines[arm.road] {
+                            Some(ref centerline) if arm.at_start => (centerline.start(), centerline.start_direction()),
+                            Some(ref centerline) => (centerline.end(), -centerline.end_direction()),
+                            None => (self.end_of(arm) + arm.direction * arm.trim, arm.direction),
+                        };
+                        let right = direction.orthogonal_right();
+                        (position + right * half_width, position - right * half_width, direction)
+                    })
+                    .collect::<Vec<_>>();
+
+                let mut outline = Vec::new();
+
+                for (i, &(right, left, direction)) in corners.iter().enumerate() {
+                    let (next_right, _, next_direction) = corners[(i + 1) % corners.len()];
+                    outline.push(right);
+                    outline.push(left);
+                    outline.extend(curb_corner(left, direction, next_right, next_direction, self.curb_radius));
+                }
+
+                outline.push(outline[0]);
+
+                let boundary = ClosedLinePath::new(LinePath::new(outline)?)?;
+                let mut mesh = Mesh::from_area(&Area::new_simple(boundary));
+                for vertex in mesh.vertices.iter_mut() {
+                    vertex.position[2] = self.z;
+                }
+                Some(mesh)
+            })
+            .collect()
+    }
+
+    /// All road bands and junctions in one mesh
+    pub fn to_mesh(&self) -> Mesh {
+        self.road_meshes().into_iter().chain(self.junction_meshes()).sum()
+    }
+}
+
+fn cross(a: V2, b: V2) -> N {
+    a.x * b.y - a.y * b.x
+}
+
+/// The inner points of the outline from one arm's left corner to the next arm's right corner.
+/// Where the gap between the arms is convex, the edges are joined by a curb rounded with
+/// `curb_radius`, where it is reflex they are extended to a square corner.
+fn curb_corner(from: P2, from_direction: V2, to: P2, to_direction: V2, curb_radius: N) -> Vec<P2> {
+    // from + s * from_direction = to + t * to_direction
+    let determinant = cross(from_direction, -to_direction);
+    if determinant.abs() < 0.001 {
+        return Vec::new();
+    }
+
+    let offset = to - from;
+    let s = cross(offset, -to_direction) / determinant;
+    let t = cross(from_direction, offset) / determinant;
+    let intersection = from + from_direction * s;
+
+    let sin = cross(from_direction, to_direction);
+    let cos = from_direction.dot(&to_direction);
+
+    if sin < 0.0 {
+        // arms that almost point the same way would need a very long spike
+        return if cos < MAX_SQUARE_CORNER_COS && s <= 0.0 && t <= 0.0 {
+            vec![intersection]
+        } else {
+            Vec::new()
+        };
+    }
+
+    // the edges only meet inside the junction if both extend back into it
+    if s > 0.0 || t > 0.0 {
+        return Vec::new();
+    }
+
+    // where a circle touching both edges meets them, as far from the intersection as
+    // the trimmed ends allow
+    let tangent_length = (curb_radius * (1.0 + cos) / sin).min(-s).min(-t);
+    let radius = tangent_length * sin / (1.0 + cos);
+    let center = intersection
+        + (from_direction + to_direction).normalize() * (tangent_length * tangent_length + radius * radius).sqrt();
+    let (from_tangent, to_tangent) = (
+        intersection + from_direction * tangent_length - center,
+        intersection + to_direction * tangent_length - center,
+    );
+
+    (0..=CURB_SEGMENTS)
+        .map(|i| {
+            let u = i as N / CURB_SEGMENTS as N;
+            center + (from_tangent * (1.0 - u) + to_tangent * u).normalize() * radius
+        })
+        .collect()
+}
@@ -0,0 +1,224 @@
+use descartes::{N, P2, V2, Area, PointContainer, HasBoundingBox, Segment};
+use crate::mesh::Instance;
+use crate::random::Rng;
+use std::collections::HashMap;
+
+// candidates tried around each active point, see Bridson,
+// "Fast Poisson Disk Sampling in Arbitrary Dimensions" (2007)
+const POISSON_ATTEMPTS: usize = 30;
+
+#[derive(Copy, Clone, Debug)]
+pub enum ScatterDistribution {
+    /// Random points that are at least `min_distance` apart, like trees in a forest
+    PoissonDisk { min_distance: N },
+    /// Points on a regular grid aligned to the world axes, like parking spots
+    Grid { spacing: N },
+    /// Grid points moved randomly by up to `jitter` (as a fraction of `spacing`) along each axis
+    JitteredGrid { spacing: N, jitter: N },
+}
+
+/// Distributes instances inside an area. The same seed always yields the same instances,
+/// bit-identical on every platform.
+pub struct Scatter {
+    pub distribution: ScatterDistribution,
+    pub seed: u64,
+    /// The minimum distance of instances to the boundaries of the area and of exclusions
+    pub boundary_distance: N,
+    pub exclusions: Vec<Area>,
+    pub random_rotation: bool,
+}
+
+impl Scatter {
+    pub fn new(distribution: ScatterDistribution, seed: u64) -> Scatter {
+        Scatter {
+            distribution,
+            seed,
+            boundary_distance: 0.0,
+            exclusions: Vec::new(),
+            random_rotation: false,
+        }
+    }
+
+    pub fn with_boundary_distance(self, boundary_distance: N) -> Scatter {
+        Scatter {
+            boundary_distance,
+            ..self
+        }
+    }
+
+    /// Leave out the inside of `area`, like paths in a park
+    pub fn excluding(mut self, area: Area) -> Scatter {
+        self.exclusions.push(area);
+        self
+    }
+
+    /// Face each instance in a random direction, instead of along the x axis
+    pub fn randomly_rotated(self) -> Scatter {
+        Scatter {
+            random_rotation: true,
+            ..self
+        }
+    }
+
+    // measured per segment, since `LinePath::distance_to` ignores the ends of segments
+    // and overestimates the distance to convex corners
+    fn distance_to_boundary(area: &Area, point: P2) -> N {
+        area.primitives
+            .iter()
+            .flat_map(|primitive| primitive.boundary.path().segments())
+            .map(|segment| {
+                let (start, end) = (segment.start(), segment.end());
+                let along = end - start;
+                let t = ((point - start).dot(&along) / along.norm_squared()).clamp(0.0, 1.0);
+                (start + along * t - point).norm()
+            })
+            .fold(N::INFINITY, N::min)
+    }
+
+    fn accepts(&self, area: &Area, point: P2) -> bool {
+        area.contains(point)
+            && Scatter::distance_to_boundary(area, point) >= self.boundary_distance
+            && self.exclusions.iter().all(|exclusion| {
+                !exclusion.contains(point)
+                    && Scatter::distance_to_boundary(exclusion, point) >= self.boundary_distance
+            })
+    }
+
+    fn candidate_points(&self, area: &Area) -> Vec<P2> {
+        let bbox = area.bounding_box();
+
+        match self.distribution {
+            ScatterDistribution::Grid { spacing } => grid_points(bbox.min, bbox.max, spacing, 0.0, self.seed),
+            ScatterDistribution::JitteredGrid { spacing, jitter } => {
+                grid_points(bbox.min, bbox.max, spacing, jitter, self.seed)
+            }
+            ScatterDistribution::PoissonDisk { min_distance } => {
+                poisson_disk_points(bbox.min, bbox.max, min_distance, self.seed)
+            }
+        }
+    }
+
+    /// Instances at height `z` inside the area
+    pub fn instances(&self, area: &Area, z: N, color: [f32; 3]) -> Vec<Instance> {
+        let mut rotation_rng = Rng::for_stream(self.seed, 1);
+
+        self.candidate_points(area)
+            .into_iter()
+            .filter(|point| self.accepts(area, *point))
+            .map(|point| {
+                let instance_direction = if self.random_rotation {
+                    let direction = offset_in_annulus(&mut rotation_rng, 0.5, 1.0).normalize();
+                    [direction.x, direction.y]
+                } else {
+                    [1.0, 0.0]
+                };
+
+                Instance {
+                    instance_position: [point.x, point.y, z],
+                    instance_direction,
+                    instance_color: color,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Grid points within the bounds, aligned to multiples of `spacing`, so they stay
+/// in place when the area changes. Each cell is jittered with its own random stream.
+fn grid_points(min: P2, max: P2, spacing: N, jitter: N, seed: u64) -> Vec<P2> {
+    if spacing <= 0.0 {
+        return Vec::new();
+    }
+
+    let (first_x, last_x) = ((min.x / spacing).floor() as i64, (max.x / spacing).ceil() as i64);
+    let (first_y, last_y) = ((min.y / spacing).floor() as i64, (max.y / spacing).ceil() as i64);
+
+    (first_y..=last_y)
+        .flat_map(|y| (first_x..=last_x).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let mut point = P2::new(x as N * spacing, y as N * spacing);
+            if jitter > 0.0 {
+                let cell = ((x as u64) << 32) ^ (y as u64 & 0xFFFF_FFFF);
+                let mut rng = Rng::for_stream(seed, cell);
+                point.x += rng.in_range(-1.0, 1.0) * jitter * spacing;
+                point.y += rng.in_range(-1.0, 1.0) * jitter * spacing;
+            }
+            point
+        })
+        .collect()
+}
+
+/// A random offset at least `inner` and less than `outer` long, by rejection sampling,
+/// since `sin` and `cos` don't give the same results on every platform
+fn offset_in_annulus(rng: &mut Rng, inner: N, outer: N) -> V2 {
+    loop {
+        let offset = V2::new(rng.in_range(-outer, outer), rng.in_range(-outer, outer));
+        let squared_length = offset.norm_squared();
+        if squared_length >= inner * inner && squared_length < outer * outer {
+            return offset;
+        }
+    }
+}
+
+fn poisson_disk_points(min: P2, max: P2, min_distance: N, seed: u64) -> Vec<P2> {
+    if min_distance <= 0.0 {
+        return Vec::new();
+    }
+
+    let mut rng = Rng::new(seed);
+    let cell_width = min_distance / (2.0 as N).sqrt();
+    let cell_of = |point: P2| {
+        (
+            ((point.x - min.x) / cell_width).floor() as i64,
+            ((point.y - min.y) / cell_width).floor() as i64,
+        )
+    };
+
+    // each cell contains at most one point
+    let mut cells = HashMap::<(i64, i64), usize>::new();
+    let mut points = Vec::new();
+    let mut active = Vec::new();
+
+    let first = P2::new(rng.in_range(min.x, max.x), rng.in_range(min.y, max.y));
+    cells.insert(cell_of(first), 0);
+    points.push(first);
+    active.push(0);
+
+    while !active.is_empty() {
+        let active_index = (rng.next_u64() % active.len() as u64) as usize;
+        let center = points[active[active_index]];
+        let mut found = false;
+
+        for _ in 0..POISSON_ATTEMPTS {
+            let candidate = center + offset_in_annulus(&mut rng, min_distance, 2.0 * min_distance);
+
+            if candidate.x < min.x || candidate.x > max.x || candidate.y < min.y || candidate.y > max.y {
+                continue;
+            }
+
+            let (cell_x, cell_y) = cell_of(candidate);
+            let too_close = (-2..=2).any(|dy| {
+                (-2..=2).any(|dx| {
+                    cells
+                        .get(&(cell_x + dx, cell_y + dy))
+                        .map(|other| (points[*other] - candidate).norm() < min_distance)
+                        .unwrap_or(false)
+                })
+            });
+
+            if !too_close {
+                cells.insert((cell_x, cell_y), points.len());
+                active.push(points.len());
+                points.push(candidate);
+                found = true;
+                break;
+            }
+        }
+
+        if !found {
+            active.swap_remove(active_index);
+        }
+    }
+
+    points
+}
@@ -1,11 +1,25 @@
-use crate::mesh::{Mesh, Vertex};
-use descartes::{P2, N, LinePath, PrimitiveArea, Band};
+use crate::mesh::{Mesh, Vertex, WELD_EPSILON, is_mirroring, quadratic_bezier_point, triangle_normal, triangle_points};
+use crate::bounds::Bounds;
+use crate::clip::loop_cap;
+use crate::random::Variation;
+use descartes::{P2, P3, V2, M4, N, LinePath, PrimitiveArea, Band, Segment, RoughEq, WithUniqueOrthogonal};
 use lyon_tessellation::math::point as lyon_point;
 use lyon_tessellation::path::iterator::PathIter;
 use lyon_tessellation::path::PathEvent;
 use lyon_tessellation::{FillOptions, FillTessellator};
+use std::collections::HashMap;
 use std::rc::Rc;
 
+const MAX_CURVE_SEGMENTS: usize = 1024;
+// so that arcs stay round even with a coarse tolerance
+const MAX_ARC_STEP: N = ::std::f32::consts::PI / 4.0;
+
+/// Curves are split into segments of equal parameter length, so their points are spread
+/// evenly and spanned surfaces between a curve and its extrusion get even triangles
+fn curve_segments(ideal: N) -> usize {
+    (ideal.ceil() as usize).clamp(1, MAX_CURVE_SEGMENTS)
+}
+
 pub struct SculptLine {
     pub path: LinePath,
     pub z: N,
@@ -18,6 +32,81 @@ impl SculptLine {
         }
     }
 
+    /// A circular arc, counter-clockwise if `end_angle` is larger than `start_angle`.
+    /// No point of the arc is further than `tolerance` from the resulting line.
+    pub fn arc(center: P2, radius: N, start_angle: N, end_angle: N, z: N, tolerance: N) -> Option<SculptLine> {
+        if radius <= 0.0 || tolerance <= 0.0 {
+            return None;
+        }
+
+        let sweep = end_angle - start_angle;
+        // the largest angle whose chord stays within tolerance of the arc
+        let max_step = 2.0 * (1.0 - (tolerance / radius).min(1.0)).acos();
+        let n_segments = curve_segments((sweep.abs() / max_step).max(sweep.abs() / MAX_ARC_STEP));
+
+        let mut points = (0..=n_segments)
+            .map(|i| {
+                let angle = start_angle + sweep * (i as N / n_segments as N);
+                center + radius * V2::new(angle.cos(), angle.sin())
+            })
+            .collect::<Vec<_>>();
+
+        if sweep.abs() >= 2.0 * ::std::f32::consts::PI - 0.000_01 {
+            // make full circles exactly closed
+            points[n_segments] = points[0];
+        }
+
+        Some(SculptLine::new(LinePath::new(points)?, z))
+    }
+
+    /// A closed, counter-clockwise circle, like the outline of a round tower
+    pub fn circle(center: P2, radius: N, z: N, tolerance: N) -> Option<SculptLine> {
+        SculptLine::arc(center, radius, 0.0, 2.0 * ::std::f32::consts::PI, z, tolerance)
+    }
+
+    /// A quadratic Bézier curve. No point of the curve is further than `tolerance`
+    /// from the resulting line.
+    pub fn quadratic_bezier(from: P2, control: P2, to: P2, z: N, tolerance: N) -> Option<SculptLine> {
+        if tolerance <= 0.0 {
+            return None;
+        }
+
+        // the deviation of n uniform segments is at most |B''| / (8 n²)
+        let max_second_derivative = 2.0 * (from.coords - 2.0 * control.coords + to.coords).norm();
+        let n_segments = curve_segments((max_second_derivative / (8.0 * tolerance)).sqrt());
+
+        let points =
+            (0..=n_segments).map(|i| quadratic_bezier_point(from, control, to, i as N / n_segments as N));
+
+        Some(SculptLine::new(LinePath::new(points.collect())?, z))
+    }
+
+    /// A cubic Bézier curve. No point of the curve is further than `tolerance`
+    /// from the resulting line.
+    pub fn cubic_bezier(from: P2, control_1: P2, control_2: P2, to: P2, z: N, tolerance: N) -> Option<SculptLine> {
+        if tolerance <= 0.0 {
+            return None;
+        }
+
+        // B'' is linear in t, so it is largest at one of the ends
+        let max_second_derivative = 6.0 * (from.coords - 2.0 * control_1.coords + control_2.coords)
+            .norm()
+            .max((control_1.coords - 2.0 * control_2.coords + to.coords).norm());
+        let n_segments = curve_segments((max_second_derivative / (8.0 * tolerance)).sqrt());
+
+        let points = (0..=n_segments).map(|i| {
+            let t = i as N / n_segments as N;
+            let u = 1.0 - t;
+            let a = from.coords * (u * u * u);
+            let b = control_1.coords * (3.0 * u * u * t);
+            let c = control_2.coords * (3.0 * u * t * t);
+            let d = to.coords * (t * t * t);
+            P2::from(a + b + c + d)
+        });
+
+        Some(SculptLine::new(LinePath::new(points.collect())?, z))
+    }
+
     pub fn extrude(line: &Rc<Self>, up: N, out: N) -> Option<(SpannedSurface, Rc<SculptLine>)> {
         let upper_line = Rc::new(SculptLine {
             path: if out == 0.0 {
@@ -43,12 +132,27 @@ impl SculptLine {
             maybe_path.map(|path| Rc::new(SculptLine::new(path, self.z)))
         }).collect()
     }
+
+    /// Like `subdivide`, with each weight varied by up to `max_deviation` of itself,
+    /// e.g. for irregular window spacing
+    pub fn subdivide_varied(&self, weights: &[N], max_deviation: N, variation: &mut Variation) -> Vec<Rc<SculptLine>> {
+        let varied_weights = weights.iter()
+            .map(|weight| variation.around(*weight, weight * max_deviation).max(0.0))
+            .collect::<Vec<_>>();
+        self.subdivide(&varied_weights)
+    }
 }
 
+/// A surface spanned between two lines, swept from `left_line` to `right_line`.
+///
+/// If the lines are closed, the surface is assumed to be part of a closed shape
+/// and faces away from it. Open surfaces face to the left of their path direction,
+/// which makes bands between two lines at the same height face up.
 #[derive(Clone)]
 pub struct SpannedSurface {
     pub left_line: Rc<SculptLine>,
     pub right_line: Rc<SculptLine>,
+    pub double_sided: bool,
 }
 
 impl SpannedSurface {
@@ -56,22 +160,41 @@ impl SpannedSurface {
         SpannedSurface {
             left_line,
             right_line,
+            double_sided: false,
+        }
+    }
+
+    /// Emit back faces as well, for thin elements like fences
+    pub fn double_sided(self) -> SpannedSurface {
+        SpannedSurface {
+            double_sided: true,
+            ..self
         }
     }
 }
 
+/// A flat, horizontal surface that faces up, unless it is the bottom of a closed shape,
+/// i.e. its boundary is the lower line of a spanned surface, like the floor below extruded walls
 #[derive(Clone)]
 pub struct FlatSurface {
     pub boundary: Rc<SculptLine>,
+    pub double_sided: bool,
 }
 
 impl FlatSurface {
+    pub fn new(boundary: Rc<SculptLine>) -> Self {
+        FlatSurface {
+            boundary,
+            double_sided: false,
+        }
+    }
+
     pub fn from_primitive_area(area: PrimitiveArea, z: N) -> Self {
         let boundary = Rc::new(SculptLine {
             path: area.boundary.path().clone(),
             z,
         });
-        FlatSurface { boundary }
+        FlatSurface::new(boundary)
     }
 
     pub fn from_band(path: LinePath, width_left: N, width_right: N, z: N) -> Self {
@@ -79,16 +202,27 @@ impl FlatSurface {
             path: Band::new_asymmetric(path, width_left, width_right).outline().0,
             z
         });
-        FlatSurface { boundary }
+        FlatSurface::new(boundary)
+    }
+
+    /// Emit back faces as well, so the surface is visible from below
+    pub fn double_sided(self) -> Self {
+        FlatSurface {
+            double_sided: true,
+            ..self
+        }
     }
 
     pub fn extrude(&self, up: N, out: N) -> Option<(SpannedSurface, FlatSurface)> {
         let (spanned_surface, upper_boundary) = SculptLine::extrude(&self.boundary, up, out)?;
-        let upper_surface = FlatSurface {
-            boundary: upper_boundary.clone(),
-        };
+        let upper_surface = FlatSurface::new(upper_boundary);
         Some((spanned_surface, upper_surface))
     }
+
+    /// Like `extrude`, with the height varied by up to `max_deviation`
+    pub fn extrude_varied(&self, up: N, out: N, max_deviation: N, variation: &mut Variation) -> Option<(SpannedSurface, FlatSurface)> {
+        self.extrude(variation.around(up, max_deviation), out)
+    }
 }
 
 #[derive(Clone)]
@@ -150,13 +284,36 @@ impl SkeletonSpine {
         (RoofSurface{spine: self.clone(), height, gable_depth_front, gable_depth_back}, GableSurface{spine: self.clone(), height, gable_depth_front, gable_depth_back})
     }
 
+    /// Like `roof`, with the height varied by up to `max_deviation`
+    pub fn roof_varied(&self, height: N, gable_depth_front: N, gable_depth_back: N, max_deviation: N, variation: &mut Variation) -> (RoofSurface, GableSurface) {
+        self.roof(variation.around(height, max_deviation), gable_depth_front, gable_depth_back)
+    }
+
     pub fn to_flat_surface(&self) -> FlatSurface {
-        FlatSurface {
-            boundary: self.boundary.clone()
-        }
+        FlatSurface::new(self.boundary.clone())
+    }
+
+    fn transformed(&self, transformer: &mut LineTransformer) -> Option<SkeletonSpine> {
+        // mirroring reverses all lines, which turns the old back into the new front
+        let (front, back) = if transformer.mirroring {
+            (&self.back, &self.front)
+        } else {
+            (&self.front, &self.back)
+        };
+
+        Some(SkeletonSpine {
+            center: transformer.transform(&self.center)?,
+            width: self.width * transformer.horizontal_scale,
+            boundary: transformer.transform(&self.boundary)?,
+            left: transformer.transform(&self.left)?,
+            front: transformer.transform(front)?,
+            right: transformer.transform(&self.right)?,
+            back: transformer.transform(back)?,
+        })
     }
 }
 
+#[derive(Clone)]
 pub struct RoofSurface {
     spine: SkeletonSpine,
     height: N,
@@ -164,6 +321,7 @@ pub struct RoofSurface {
     gable_depth_back: N
 }
 
+#[derive(Clone)]
 pub struct GableSurface {
     spine: SkeletonSpine,
     height: N,
@@ -171,6 +329,7 @@ pub struct GableSurface {
     gable_depth_back: N
 }
 
+#[derive(Clone)]
 pub enum Surface {
     Spanned(SpannedSurface),
     Flat(FlatSurface),
@@ -204,6 +363,67 @@ impl Into<Surface> for GableSurface {
 
 pub struct Sculpture(Vec<Surface>);
 
+/// Transforms sculpt lines while keeping lines that are shared between surfaces shared
+struct LineTransformer {
+    matrix: M4,
+    mirroring: bool,
+    horizontal_scale: N,
+    vertical_scale: N,
+    transformed_lines: HashMap<*const SculptLine, Rc<SculptLine>>,
+}
+
+impl LineTransformer {
+    /// Only accepts transformations that keep horizontal lines horizontal
+    /// and don't turn the sculpture upside down
+    fn new(matrix: M4) -> Option<LineTransformer> {
+        let m = |row: usize, column: usize| matrix[(row, column)];
+        let keeps_z_vertical = [m(0, 2), m(1, 2), m(2, 0), m(2, 1), m(3, 0), m(3, 1), m(3, 2)]
+            .iter()
+            .all(|entry| entry.abs() < 0.000_01)
+            && m(2, 2) > 0.0
+            && (m(3, 3) - 1.0).abs() < 0.000_01;
+
+        if keeps_z_vertical {
+            Some(LineTransformer {
+                mirroring: is_mirroring(&matrix),
+                horizontal_scale: (m(0, 0) * m(1, 1) - m(0, 1) * m(1, 0)).abs().sqrt(),
+                vertical_scale: m(2, 2),
+                matrix,
+                transformed_lines: HashMap::new(),
+            })
+        } else {
+            None
+        }
+    }
+
+    fn transform(&mut self, line: &Rc<SculptLine>) -> Option<Rc<SculptLine>> {
+        let key = &**line as *const SculptLine;
+
+        if let Some(transformed_line) = self.transformed_lines.get(&key) {
+            return Some(transformed_line.clone());
+        }
+
+        let matrix = &self.matrix;
+        let path = LinePath::new(
+            line.path
+                .points
+                .iter()
+                .map(|point| {
+                    let transformed = matrix.transform_point(&P3::new(point.x, point.y, line.z));
+                    P2::new(transformed.x, transformed.y)
+                })
+                .collect(),
+        )?;
+        // keep "left of the path direction" on the same side of the geometry
+        let path = if self.mirroring { path.reverse() } else { path };
+        let z = matrix.transform_point(&P3::new(0.0, 0.0, line.z)).z;
+
+        let transformed_line = Rc::new(SculptLine::new(path, z));
+        self.transformed_lines.insert(key, transformed_line.clone());
+        Some(transformed_line)
+    }
+}
+
 fn to_vertex(point: &P2, z: N) -> Vertex {
     Vertex {
         position: [point.x, point.y, z],
@@ -244,6 +464,195 @@ fn strip_indices(left_start_i: usize, left_len: usize, right_start_i: usize, rig
     }
 }
 
+/// Surfaces with less area than these ratios of the square of a sculpture's
+/// bounding sphere radius are dropped at LOD levels 1 and 2
+const LOD_MIN_AREA_RATIOS: [N; 2] = [0.01, 0.04];
+
+/// The area of a surface's mesh, so large but thin surfaces like main walls
+/// are kept while small ones like bevels and dormers are dropped
+fn surface_area(surface: &Surface) -> N {
+    let mesh = Sculpture::new(vec![surface.clone()]).to_mesh();
+    mesh.indices
+        .chunks(3)
+        .map(|triangle| {
+            let [a, b, c] = triangle_points(&mesh, triangle);
+            triangle_normal(a, b, c).norm() / 2.0
+        })
+        .sum()
+}
+
+/// Edges used by only one triangle, in the direction they have in that triangle
+fn open_edges(mesh: &Mesh) -> Vec<(u16, u16)> {
+    let triangle_edges = || {
+        mesh.indices
+            .chunks(3)
+            .flat_map(|triangle| (0..3).map(move |i| (triangle[i], triangle[(i + 1) % 3])))
+    };
+
+    let mut uses = HashMap::<(u16, u16), usize>::new();
+    for (a, b) in triangle_edges() {
+        *uses.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+    }
+
+    triangle_edges().filter(|(a, b)| uses[&(*a.min(b), *a.max(b))] == 1).collect()
+}
+
+/// Closes the holes that dropping surfaces left in `simplified`,
+/// but not the ones `full` already has, like the open bottom of walls
+fn with_holes_filled(mut simplified: Mesh, full: &Mesh) -> Mesh {
+    let point = |mesh: &Mesh, index: u16| {
+        let [x, y, z] = mesh.vertices[index as usize].position;
+        P3::new(x, y, z)
+    };
+    let cell_of = |a: P3, b: P3| {
+        let center = (a.coords + b.coords) / 2.0;
+        (
+            (center.x / WELD_EPSILON).round() as i64,
+            (center.y / WELD_EPSILON).round() as i64,
+            (center.z / WELD_EPSILON).round() as i64,
+        )
+    };
+
+    // open edges of the full mesh by the cell of their center
+    let mut full_open_edges = HashMap::<(i64, i64, i64), Vec<(P3, P3)>>::new();
+    for (a, b) in open_edges(full) {
+        let (a, b) = (point(full, a), point(full, b));
+        full_open_edges.entry(cell_of(a, b)).or_default().push((a, b));
+    }
+
+    let was_open = |a: P3, b: P3| {
+        let (x, y, z) = cell_of(a, b);
+        (-1..=1)
+            .flat_map(|dx| (-1..=1).flat_map(move |dy| (-1..=1).map(move |dz| (x + dx, y + dy, z + dz))))
+            .filter_map(|cell| full_open_edges.get(&cell))
+            .flat_map(|edges| edges.iter())
+            .any(|(c, d)| {
+                let is_near = |p: P3, q: P3| (p - q).norm() <= WELD_EPSILON;
+                (is_near(a, *c) && is_near(b, *d)) || (is_near(a, *d) && is_near(b, *c))
+            })
+    };
+
+    // a hole runs against the open edges around it, so its cap faces the same way
+    let mut next_around_hole = HashMap::<u16, Vec<u16>>::new();
+    for (a, b) in open_edges(&simplified) {
+        if !was_open(point(&simplified, a), point(&simplified, b)) {
+            next_around_hole.entry(b).or_default().push(a);
+        }
+    }
+
+    let mut caps = Vec::new();
+    let starts = next_around_hole.keys().cloned().collect::<Vec<_>>();
+
+    for start in starts {
+        let mut hole = vec![start];
+        while let Some(next) = next_around_hole.get_mut(hole.last().unwrap()).and_then(Vec::pop) {
+            if next == start {
+                caps.push(loop_cap(&hole.iter().map(|index| point(&simplified, *index)).collect::<Vec<_>>()));
+                break;
+            }
+            hole.push(next);
+        }
+    }
+
+    if !caps.is_empty() {
+        simplified += caps.into_iter().sum::<Mesh>();
+        simplified.weld(WELD_EPSILON);
+    }
+    simplified
+}
+
+/// Spine points that deviate less than this ratio of the spine's width from
+/// a straight line through their neighbors are dropped at LOD level 2
+const LOD_SPINE_TOLERANCE_RATIO: N = 0.1;
+
+/// The spine with nearly collinear points dropped (Ramer-Douglas-Peucker),
+/// so roofs keep their shape but have fewer ridge segments
+fn simplified_spine(spine: &SkeletonSpine) -> SkeletonSpine {
+    fn keep_points(points: &[P2], tolerance: N, kept: &mut Vec<P2>) {
+        let (start, end) = (points[0], points[points.len() - 1]);
+        let along = end - start;
+        let distance_to_line = |point: &P2| {
+            let t = ((point - start).dot(&along) / along.norm_squared().max(N::EPSILON)).clamp(0.0, 1.0);
+            (start + along * t - point).norm()
+        };
+
+        let farthest = (1..points.len() - 1).map(|i| (i, distance_to_line(&points[i]))).fold(
+            None,
+            |farthest: Option<(usize, N)>, candidate| match farthest {
+                Some(farthest) if farthest.1 >= candidate.1 => Some(farthest),
+                _ => Some(candidate),
+            },
+        );
+
+        match farthest {
+            Some((i, distance)) if distance > tolerance => {
+                keep_points(&points[..=i], tolerance, kept);
+                keep_points(&points[i..], tolerance, kept);
+            }
+            _ => kept.push(start),
+        }
+    }
+
+    let center_path = &spine.center.path;
+    if center_path.points.len() <= 2 {
+        return spine.clone();
+    }
+
+    let mut points = Vec::new();
+    keep_points(&center_path.points, LOD_SPINE_TOLERANCE_RATIO * spine.width, &mut points);
+    points.push(center_path.end());
+
+    if points.len() == center_path.points.len() {
+        return spine.clone();
+    }
+
+    LinePath::new(points)
+        .and_then(|path| SkeletonSpine::new(Rc::new(SculptLine::new(path, spine.center.z)), spine.width))
+        .unwrap_or_else(|| spine.clone())
+}
+
+fn is_closed(path: &LinePath) -> bool {
+    path.points.len() >= 3 && path.start().rough_eq_by(path.end(), 0.001)
+}
+
+/// Positive for counter-clockwise paths
+fn signed_area(path: &LinePath) -> N {
+    path.segments()
+        .map(|segment| segment.start().x * segment.end().y - segment.end().x * segment.start().y)
+        .sum::<N>()
+        / 2.0
+}
+
+/// Checks whether a strip built by `strip_indices` along a closed left line faces
+/// into the closed shape instead of away from it
+fn strip_faces_inward(strip: &Mesh, left_path: &LinePath) -> bool {
+    let outward_sign = if signed_area(left_path) > 0.0 { 1.0 } else { -1.0 };
+    let outward_directions = left_path
+        .segments()
+        .map(|segment| outward_sign * segment.direction().orthogonal_right())
+        .collect::<Vec<_>>();
+
+    let mut outwardness = 0.0;
+    let mut total_area = 0.0;
+
+    // strip_indices emits two triangles per segment of the left line
+    for (i, triangle) in strip.indices.chunks(3).enumerate() {
+        let [a, b, c] = triangle_points(strip, triangle);
+        let normal = triangle_normal(a, b, c);
+        let outward = outward_directions[(i / 2).min(outward_directions.len() - 1)];
+        outwardness += normal.x * outward.x + normal.y * outward.y;
+        total_area += normal.norm();
+    }
+
+    if outwardness.abs() > 0.01 * total_area {
+        outwardness < 0.0
+    } else {
+        // horizontal ring: a strip swept upwards around a clockwise line faces
+        // away from the shape, one around a counter-clockwise line into it
+        outward_sign > 0.0
+    }
+}
+
 impl Sculpture {
     pub fn new(surfaces: Vec<Surface>) -> Self {
         Sculpture(surfaces)
@@ -253,6 +662,104 @@ impl Sculpture {
         self.0.push(surface);
     }
 
+    /// Transforms the sculpture by a matrix or an isometry, which may only translate,
+    /// rotate and scale along the z axis and scale or mirror horizontally, since
+    /// sculpt lines are always horizontal. Returns `None` for other transformations.
+    pub fn transformed<T: Into<M4>>(&self, transformation: T) -> Option<Sculpture> {
+        let mut transformer = LineTransformer::new(transformation.into())?;
+
+        let surfaces = self.0.iter().map(|surface| {
+            Some(match surface {
+                Surface::Spanned(spanned_surface) => Surface::Spanned(SpannedSurface {
+                    left_line: transformer.transform(&spanned_surface.left_line)?,
+                    right_line: transformer.transform(&spanned_surface.right_line)?,
+                    double_sided: spanned_surface.double_sided,
+                }),
+                Surface::Flat(flat_surface) => Surface::Flat(FlatSurface {
+                    boundary: transformer.transform(&flat_surface.boundary)?,
+                    double_sided: flat_surface.double_sided,
+                }),
+                Surface::Roof(roof_surface) => {
+                    let (gable_depth_front, gable_depth_back) = if transformer.mirroring {
+                        (roof_surface.gable_depth_back, roof_surface.gable_depth_front)
+                    } else {
+                        (roof_surface.gable_depth_front, roof_surface.gable_depth_back)
+                    };
+                    Surface::Roof(RoofSurface {
+                        spine: roof_surface.spine.transformed(&mut transformer)?,
+                        height: roof_surface.height * transformer.vertical_scale,
+                        gable_depth_front: gable_depth_front * transformer.horizontal_scale,
+                        gable_depth_back: gable_depth_back * transformer.horizontal_scale,
+                    })
+                }
+                Surface::Gable(gable_surface) => {
+                    let (gable_depth_front, gable_depth_back) = if transformer.mirroring {
+                        (gable_surface.gable_depth_back, gable_surface.gable_depth_front)
+                    } else {
+                        (gable_surface.gable_depth_front, gable_surface.gable_depth_back)
+                    };
+                    Surface::Gable(GableSurface {
+                        spine: gable_surface.spine.transformed(&mut transformer)?,
+                        height: gable_surface.height * transformer.vertical_scale,
+                        gable_depth_front: gable_depth_front * transformer.horizontal_scale,
+                        gable_depth_back: gable_depth_back * transformer.horizontal_scale,
+                    })
+                }
+            })
+        }).collect::<Option<Vec<_>>>()?;
+
+        Some(Sculpture(surfaces))
+    }
+
+    /// The bounds of the sculpture, computed from its lines without building a mesh
+    pub fn bounds(&self) -> Option<Bounds> {
+        fn line_points(line: &SculptLine) -> impl Iterator<Item = P3> + '_ {
+            line.path.points.iter().map(move |point| P3::new(point.x, point.y, line.z))
+        }
+
+        fn spine_points(spine: &SkeletonSpine, height: N) -> impl Iterator<Item = P3> + '_ {
+            line_points(&spine.boundary).chain(
+                spine.center.path.points.iter()
+                    .map(move |point| P3::new(point.x, point.y, spine.center.z + height))
+            )
+        }
+
+        let points = self.0.iter().flat_map(|surface| -> Box<dyn Iterator<Item = P3> + '_> {
+            match surface {
+                Surface::Spanned(spanned_surface) => Box::new(
+                    line_points(&spanned_surface.left_line)
+                        .chain(line_points(&spanned_surface.right_line))
+                ),
+                Surface::Flat(flat_surface) => Box::new(line_points(&flat_surface.boundary)),
+                Surface::Roof(roof_surface) => Box::new(spine_points(&roof_surface.spine, roof_surface.height)),
+                Surface::Gable(gable_surface) => Box::new(spine_points(&gable_surface.spine, gable_surface.height)),
+            }
+        }).collect::<Vec<_>>();
+
+        Bounds::from_points(&points)
+    }
+
+    /// Whether a flat surface with this boundary closes off a shape from below
+    fn is_bottom(&self, boundary: &Rc<SculptLine>) -> bool {
+        let (mut goes_up, mut goes_down) = (false, false);
+
+        for surface in self.0.iter() {
+            if let Surface::Spanned(spanned_surface) = surface {
+                let (left, right) = (&spanned_surface.left_line, &spanned_surface.right_line);
+                for (line, other_line) in [(left, right), (right, left)].iter() {
+                    if Rc::ptr_eq(line, boundary) {
+                        goes_up |= other_line.z > line.z;
+                        goes_down |= other_line.z < line.z;
+                    }
+                }
+            }
+        }
+
+        goes_up && !goes_down
+    }
+
+    /// Builds a mesh with counter-clockwise front faces that face away from closed shapes.
+    /// Surfaces sharing a line also share its vertices, degenerate triangles are dropped.
     pub fn to_mesh(&self) -> Mesh {
         let mut mesh = Mesh::empty();
 
@@ -291,7 +798,19 @@ impl Sculpture {
                     //     }).collect();
                     let indices = strip_indices(0, left_points.len(), left_points.len(), right_points.len(), false);
 
-                    mesh += Mesh::new(vertices, indices);
+                    let mut output = Mesh::new(vertices, indices);
+
+                    if is_closed(&spanned_surface.left_line.path)
+                        && strip_faces_inward(&output, &spanned_surface.left_line.path)
+                    {
+                        output.flip_winding();
+                    }
+
+                    if spanned_surface.double_sided {
+                        output.add_back_faces();
+                    }
+
+                    mesh += output;
                 }
                 Surface::Flat(flat_surface) => {
                     let first_point = flat_surface.boundary.path.points[0];
@@ -316,6 +835,16 @@ impl Sculpture {
                         vertex.position[2] = flat_surface.boundary.z;
                     }
 
+                    output.orient_upwards();
+
+                    if self.is_bottom(&flat_surface.boundary) {
+                        output.flip_winding();
+                    }
+
+                    if flat_surface.double_sided {
+                        output.add_back_faces();
+                    }
+
                     mesh += output;
                 },
                 Surface::Roof(roof_surface) => {
@@ -334,10 +863,14 @@ impl Sculpture {
                     let vertices = left_points.iter().map(|p| to_vertex(p, roof_surface.spine.center.z))
                         .chain(right_points.iter().rev().map(|p| to_vertex(p, roof_surface.spine.center.z)))
                         .chain(ridge_points.iter().map(|p| to_vertex(p, roof_surface.spine.center.z + roof_surface.height))).collect();
+                    let mut right_indices = strip_indices(left_points.len(), right_points.len(), left_points.len() + right_points.len(), ridge_points.len(), false);
+                    // the right side runs in the same direction as the left side,
+                    // but has to face the other way
+                    for triangle in right_indices.chunks_mut(3) {
+                        triangle.swap(1, 2);
+                    }
                     let indices = strip_indices(0, left_points.len(), left_points.len() + right_points.len(), ridge_points.len(), false).into_iter()
-                    .chain(
-                        strip_indices(left_points.len(), right_points.len(), left_points.len() + right_points.len(), ridge_points.len(), false)
-                    ).collect();
+                    .chain(right_indices).collect();
 
                     mesh += Mesh::new(vertices, indices);
                 }
@@ -355,13 +888,86 @@ impl Sculpture {
                         to_vertex(&left_points[0], low_z), to_vertex(&right_points[right_points.len() - 1], low_z), to_vertex(&center_back, high_z),
                         to_vertex(&left_points[left_points.len() - 1], low_z), to_vertex(&right_points[0], low_z), to_vertex(&center_front, high_z)
                     ];
-                    let indices = vec![0, 1, 2, 3, 4, 5];
+                    let indices = vec![0, 1, 2, 3, 5, 4];
 
                     mesh += Mesh::new(vertices, indices);
                 }
             }
         }
 
+        mesh.weld(WELD_EPSILON);
         mesh
     }
+
+    /// Builds a mesh with less detail for higher `level`s, keeping the silhouette:
+    ///
+    /// * level 0 is the full `to_mesh`
+    /// * level 1 drops surfaces with a small area, like dormers, openings and bevels,
+    ///   and fills the holes they leave
+    /// * level 2 drops larger surfaces and the nearly collinear points of roof spines,
+    ///   so roofs have fewer ridge segments but keep their footprint
+    /// * level 3 and above is the lowest closed outline, extruded to the full height
+    pub fn to_mesh_lod(&self, level: usize) -> Mesh {
+        let bounds = match self.bounds() {
+            Some(bounds) => bounds,
+            None => return Mesh::empty(),
+        };
+
+        if level == 0 {
+            return self.to_mesh();
+        } else if level > LOD_MIN_AREA_RATIOS.len() {
+            return self.footprint_box(&bounds);
+        }
+
+        let radius = bounds.bounding_sphere.radius;
+        let min_area = LOD_MIN_AREA_RATIOS[level - 1] * radius * radius;
+        let simplify_roofs = level >= 2;
+
+        let surfaces = self.0.iter()
+            .filter(|surface| surface_area(surface) >= min_area)
+            .map(|surface| match surface {
+                Surface::Roof(roof_surface) if simplify_roofs => Surface::Roof(RoofSurface {
+                    spine: simplified_spine(&roof_surface.spine),
+                    ..roof_surface.clone()
+                }),
+                Surface::Gable(gable_surface) if simplify_roofs => Surface::Gable(GableSurface {
+                    spine: simplified_spine(&gable_surface.spine),
+                    ..gable_surface.clone()
+                }),
+                other => other.clone(),
+            })
+            .collect();
+
+        with_holes_filled(Sculpture(surfaces).to_mesh(), &self.to_mesh())
+    }
+
+    /// The lowest (and then largest) closed outline, extruded to the top of the sculpture,
+    /// or the bounding box if there is no closed outline
+    fn footprint_box(&self, bounds: &Bounds) -> Mesh {
+        let outlines = self.0.iter().flat_map(|surface| match surface {
+            Surface::Spanned(spanned_surface) => vec![&spanned_surface.left_line, &spanned_surface.right_line],
+            Surface::Flat(flat_surface) => vec![&flat_surface.boundary],
+            Surface::Roof(RoofSurface { spine, .. }) | Surface::Gable(GableSurface { spine, .. }) => vec![&spine.boundary],
+        }).filter(|line| is_closed(&line.path));
+
+        let footprint = outlines.min_by(|a, b| {
+            a.z.partial_cmp(&b.z)
+                .unwrap_or(::std::cmp::Ordering::Equal)
+                .then_with(|| signed_area(&b.path).abs()
+                    .partial_cmp(&signed_area(&a.path).abs())
+                    .unwrap_or(::std::cmp::Ordering::Equal))
+        }).cloned().or_else(|| {
+            let (min, max) = (bounds.bounding_box.min, bounds.bounding_box.max);
+            LinePath::new(vec![
+                P2::new(min.x, min.y), P2::new(max.x, min.y), P2::new(max.x, max.y),
+                P2::new(min.x, max.y), P2::new(min.x, min.y),
+            ]).map(|path| Rc::new(SculptLine::new(path, min.z)))
+        });
+
+        footprint.and_then(|footprint| {
+            let (walls, top) = FlatSurface::new(footprint.clone())
+                .extrude(bounds.bounding_box.max.z - footprint.z, 0.0)?;
+            Some(Sculpture::new(vec![walls.into(), top.into()]).to_mesh())
+        }).unwrap_or_else(Mesh::empty)
+    }
 }
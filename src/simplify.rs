@@ -0,0 +1,287 @@
+use descartes::{N, P3, V3};
+use crate::mesh::{Mesh, triangle_normal};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+/// The sum of squared distances to a set of planes,
+/// see Garland and Heckbert, "Surface Simplification Using Quadric Error Metrics" (1997)
+#[derive(Copy, Clone)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn zero() -> Quadric {
+        Quadric([0.0; 10])
+    }
+
+    /// `normal` has to be normalized
+    fn from_plane(normal: V3, point: P3) -> Quadric {
+        let [a, b, c] = [normal.x as f64, normal.y as f64, normal.z as f64];
+        let d = -(a * point.x as f64 + b * point.y as f64 + c * point.z as f64);
+        Quadric([
+            a * a, a * b, a * c, a * d,
+            b * b, b * c, b * d,
+            c * c, c * d,
+            d * d,
+        ])
+    }
+
+    fn add(&self, other: &Quadric) -> Quadric {
+        let mut sum = self.0;
+        for (s, o) in sum.iter_mut().zip(other.0.iter()) {
+            *s += o;
+        }
+        Quadric(sum)
+    }
+
+    fn error_at(&self, point: P3) -> f64 {
+        let q = &self.0;
+        let [x, y, z] = [point.x as f64, point.y as f64, point.z as f64];
+        (q[0] * x * x + 2.0 * q[1] * x * y + 2.0 * q[2] * x * z + 2.0 * q[3] * x
+            + q[4] * y * y + 2.0 * q[5] * y * z + 2.0 * q[6] * y
+            + q[7] * z * z + 2.0 * q[8] * z
+            + q[9])
+            .max(0.0)
+    }
+}
+
+/// Collapsing the vertex `from` into the vertex `to`
+struct Collapse {
+    error: f64,
+    from: usize,
+    to: usize,
+    // the versions of both vertices this error was computed for
+    from_version: usize,
+    to_version: usize,
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Collapse) -> bool {
+        self.error == other.error
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Collapse) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    // reversed, so the heap pops the smallest error first
+    fn cmp(&self, other: &Collapse) -> Ordering {
+        other.error.partial_cmp(&self.error).unwrap_or(Ordering::Equal)
+    }
+}
+
+impl Mesh {
+    /// Reduces the number of triangles by repeatedly collapsing the edge that changes
+    /// the surface the least, until there are at most `target_n_triangles` or any further
+    /// collapse would move the surface by more than about `max_error`.
+    /// Pass `0` or `N::INFINITY` to only use the other criterion.
+    ///
+    /// Vertices on boundaries are kept, as are vertices on seams,
+    /// where neighboring triangles don't share vertices.
+    pub fn simplify(&mut self, target_n_triangles: usize, max_error: N) {
+        let positions = self
+            .vertices
+            .iter()
+            .map(|vertex| P3::new(vertex.position[0], vertex.position[1], vertex.position[2]))
+            .collect::<Vec<_>>();
+        let mut triangles = self
+            .indices
+            .chunks(3)
+            .map(|triangle| [triangle[0] as usize, triangle[1] as usize, triangle[2] as usize])
+            .collect::<Vec<_>>();
+        let mut alive = vec![true; triangles.len()];
+        let mut n_alive = triangles.len();
+
+        let mut vertex_triangles = vec![Vec::new(); positions.len()];
+        let mut quadrics = vec![Quadric::zero(); positions.len()];
+        let mut edge_uses = HashMap::<(usize, usize), usize>::new();
+
+        for (triangle_index, triangle) in triangles.iter().enumerate() {
+            let [a, b, c] = [positions[triangle[0]], positions[triangle[1]], positions[triangle[2]]];
+            let normal = triangle_normal(a, b, c);
+            let quadric = if normal.norm() > 0.0 {
+                Quadric::from_plane(normal.normalize(), a)
+            } else {
+                Quadric::zero()
+            };
+
+            for (i, vertex) in triangle.iter().enumerate() {
+                vertex_triangles[*vertex].push(triangle_index);
+                quadrics[*vertex] = quadrics[*vertex].add(&quadric);
+                let next = triangle[(i + 1) % 3];
+                *edge_uses.entry(((*vertex).min(next), (*vertex).max(next))).or_insert(0) += 1;
+            }
+        }
+
+        // seams show up as boundaries, since the vertices on both sides are distinct
+        let mut locked = vec![false; positions.len()];
+        for (&(a, b), &uses) in edge_uses.iter() {
+            if uses != 2 {
+                locked[a] = true;
+                locked[b] = true;
+            }
+        }
+
+        let mut collapsed = vec![false; positions.len()];
+        let mut versions = vec![0; positions.len()];
+        let mut candidates = BinaryHeap::new();
+
+        let neighbors = |vertex: usize, triangles: &[[usize; 3]], alive: &[bool], vertex_triangles: &[Vec<usize>]| {
+            let mut neighbors = vertex_triangles[vertex]
+                .iter()
+                .filter(|triangle_index| alive[**triangle_index])
+                .flat_map(|triangle_index| triangles[*triangle_index].to_vec())
+                .filter(|other| *other != vertex)
+                .collect::<Vec<_>>();
+            neighbors.sort();
+            neighbors.dedup();
+            neighbors
+        };
+
+        let push_candidates = |vertex: usize,
+                               neighbors: &[usize],
+                               quadrics: &[Quadric],
+                               versions: &[usize],
+                               locked: &[bool],
+                               candidates: &mut BinaryHeap<Collapse>| {
+            for &neighbor in neighbors {
+                let quadric = quadrics[vertex].add(&quadrics[neighbor]);
+                if !locked[vertex] {
+                    candidates.push(Collapse {
+                        error: quadric.error_at(positions[neighbor]),
+                        from: vertex,
+                        to: neighbor,
+                        from_version: versions[vertex],
+                        to_version: versions[neighbor],
+                    });
+                }
+                if !locked[neighbor] {
+                    candidates.push(Collapse {
+                        error: quadric.error_at(positions[vertex]),
+                        from: neighbor,
+                        to: vertex,
+                        from_version: versions[neighbor],
+                        to_version: versions[vertex],
+                    });
+                }
+            }
+        };
+
+        for vertex in 0..positions.len() {
+            let higher_neighbors = neighbors(vertex, &triangles, &alive, &vertex_triangles)
+                .into_iter()
+                .filter(|neighbor| *neighbor > vertex)
+                .collect::<Vec<_>>();
+            push_candidates(vertex, &higher_neighbors, &quadrics, &versions, &locked, &mut candidates);
+        }
+
+        let max_squared_error = (max_error as f64) * (max_error as f64);
+
+        while n_alive > target_n_triangles {
+            let Collapse { error, from, to, from_version, to_version } = match candidates.pop() {
+                Some(candidate) => candidate,
+                None => break,
+            };
+
+            if error > max_squared_error {
+                break;
+            }
+
+            if collapsed[from] || collapsed[to] || versions[from] != from_version || versions[to] != to_version {
+                continue;
+            }
+
+            // link condition: only the triangles on the edge may share both endpoints' neighbors,
+            // otherwise the collapse would create non-manifold geometry
+            let from_neighbors = neighbors(from, &triangles, &alive, &vertex_triangles);
+            if !from_neighbors.contains(&to) {
+                continue;
+            }
+            let to_neighbors = neighbors(to, &triangles, &alive, &vertex_triangles);
+            let n_shared_neighbors = from_neighbors
+                .iter()
+                .filter(|neighbor| to_neighbors.contains(neighbor))
+                .count();
+            let n_edge_triangles = vertex_triangles[from]
+                .iter()
+                .filter(|triangle_index| alive[**triangle_index] && triangles[**triangle_index].contains(&to))
+                .count();
+            if n_shared_neighbors > n_edge_triangles {
+                continue;
+            }
+
+            // don't fold any remaining triangle over
+            let flips = vertex_triangles[from]
+                .iter()
+                .filter(|triangle_index| alive[**triangle_index] && !triangles[**triangle_index].contains(&to))
+                .any(|triangle_index| {
+                    let triangle = triangles[*triangle_index];
+                    let before = triangle_normal(positions[triangle[0]], positions[triangle[1]], positions[triangle[2]]);
+                    let moved = |vertex: usize| if vertex == from { positions[to] } else { positions[vertex] };
+                    let after = triangle_normal(moved(triangle[0]), moved(triangle[1]), moved(triangle[2]));
+                    before.dot(&after) <= 0.0
+                });
+            if flips {
+                continue;
+            }
+
+            for triangle_index in vertex_triangles[from].clone() {
+                if !alive[triangle_index] {
+                    continue;
+                }
+                if triangles[triangle_index].contains(&to) {
+                    alive[triangle_index] = false;
+                    n_alive -= 1;
+                } else {
+                    for vertex in triangles[triangle_index].iter_mut() {
+                        if *vertex == from {
+                            *vertex = to;
+                        }
+                    }
+                    vertex_triangles[to].push(triangle_index);
+                }
+            }
+
+            collapsed[from] = true;
+            quadrics[to] = quadrics[to].add(&quadrics[from]);
+            versions[to] += 1;
+
+            let to_neighbors = neighbors(to, &triangles, &alive, &vertex_triangles);
+            push_candidates(to, &to_neighbors, &quadrics, &versions, &locked, &mut candidates);
+        }
+
+        let indices = triangles
+            .iter()
+            .zip(alive.iter())
+            .filter(|(_, alive)| **alive)
+            .flat_map(|(triangle, _)| triangle.iter().map(|vertex| *vertex as u16).collect::<Vec<_>>())
+            .collect();
+
+        *self = Mesh::new(self.vertices.to_vec(), indices);
+        self.remove_unused_vertices();
+    }
+
+    /// Simplified copies of the mesh, one per level of detail, with the given ratios
+    /// of the original number of triangles (like `[1.0, 0.5, 0.25]`).
+    /// Each level is simplified from the previous one, so the chain degrades gradually.
+    pub fn lod_chain(&self, triangle_ratios: &[N]) -> Vec<Mesh> {
+        let n_triangles = self.indices.len() / 3;
+        let mut current = self.clone();
+
+        triangle_ratios
+            .iter()
+            .map(|ratio| {
+                let target_n_triangles = (n_triangles as N * ratio).round() as usize;
+                if target_n_triangles < current.indices.len() / 3 {
+                    current.simplify(target_n_triangles, N::INFINITY);
+                }
+                current.clone()
+            })
+            .collect()
+    }
+}